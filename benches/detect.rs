@@ -0,0 +1,35 @@
+// Times quick_detect against a full read_secret on the same files, with and without a secret
+use std::io::Cursor;
+use std::time::Instant;
+
+use hackathon_jpeg_steganography::{quick_detect, read_secret, write_secret, WriteOptions};
+
+const CARRIER: &[u8] = include_bytes!("../docs/dove-small-in.jpg");
+const ITERATIONS: u32 = 20;
+
+fn time<T>(f: impl Fn() -> T) -> u128 {
+    let start = Instant::now();
+    for _ in 0..ITERATIONS {
+        f();
+    }
+    start.elapsed().as_micros() / ITERATIONS as u128
+}
+
+fn main() {
+    let mut embedded = Vec::new();
+    write_secret(
+        &mut Cursor::new(CARRIER),
+        &mut embedded,
+        "a secret to look for",
+        &WriteOptions::default(),
+    )
+    .unwrap();
+
+    for (name, data) in [("carrier", CARRIER), ("embedded", &embedded[..])] {
+        println!(
+            "{name}: quick_detect {} us, read_secret {} us",
+            time(|| quick_detect(data).unwrap()),
+            time(|| read_secret(&mut Cursor::new(data)).ok()),
+        );
+    }
+}
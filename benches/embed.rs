@@ -0,0 +1,32 @@
+use std::io::Cursor;
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use hackathon_jpeg_steganography::{write_secret, PreparedCarrier, WriteOptions};
+
+const CARRIER: &[u8] = include_bytes!("../docs/dove-small-in.jpg");
+const SECRETS: [&str; 4] = ["first", "second secret", "a third, longer secret", "4"];
+
+fn embed(c: &mut Criterion) {
+    let options = WriteOptions::default();
+
+    c.bench_function("write_secret x4", |b| {
+        b.iter(|| {
+            for secret in SECRETS {
+                let mut output = Vec::new();
+                write_secret(&mut Cursor::new(CARRIER), &mut output, secret, &options).unwrap();
+            }
+        })
+    });
+
+    c.bench_function("PreparedCarrier::embed x4", |b| {
+        b.iter(|| {
+            let carrier = PreparedCarrier::new(&mut Cursor::new(CARRIER), &options).unwrap();
+            for secret in SECRETS {
+                carrier.embed(secret).unwrap();
+            }
+        })
+    });
+}
+
+criterion_group!(benches, embed);
+criterion_main!(benches);
@@ -0,0 +1,43 @@
+// Counts what a single embed allocates, as the entropy re-encode's buffers dominate it
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use hackathon_jpeg_steganography::{PreparedCarrier, WriteOptions};
+
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+static ALLOCATED_BYTES: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        ALLOCATED_BYTES.fetch_add(layout.size(), Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+const CARRIER: &[u8] = include_bytes!("../docs/dove-small-in.jpg");
+
+fn main() {
+    let carrier = PreparedCarrier::new(&mut &CARRIER[..], &WriteOptions::default()).unwrap();
+
+    let start = std::time::Instant::now();
+    ALLOCATIONS.store(0, Ordering::Relaxed);
+    ALLOCATED_BYTES.store(0, Ordering::Relaxed);
+    carrier.embed("secret").unwrap();
+
+    println!(
+        "embed: {} allocations, {} bytes allocated, {} us",
+        ALLOCATIONS.load(Ordering::Relaxed),
+        ALLOCATED_BYTES.load(Ordering::Relaxed),
+        start.elapsed().as_micros(),
+    );
+}
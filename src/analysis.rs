@@ -0,0 +1,55 @@
+// Comparisons between a carrier and what embedding made of it, for checking which parts of the file
+// a write touched
+
+/// The offsets at which `modified` differs from `original`. Where one is longer than the other,
+/// every offset past the end of the shorter one counts as differing too: byte stuffing in the
+/// re-encoded scan can change the file's length.
+pub fn diff_offsets(original: &[u8], modified: &[u8]) -> Vec<usize> {
+    let common = original.len().min(modified.len());
+    let longest = original.len().max(modified.len());
+
+    original
+        .iter()
+        .zip(modified)
+        .enumerate()
+        .filter(|(_, (a, b))| a != b)
+        .map(|(offset, _)| offset)
+        .chain(common..longest)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::jpeg::test_image::TestImage;
+    use crate::{write_secret, WriteOptions};
+
+    #[test]
+    fn test_diff_offsets() {
+        assert_eq!(diff_offsets(&[1, 2, 3], &[1, 2, 3]), Vec::<usize>::new());
+        assert_eq!(diff_offsets(&[1, 2, 3, 4], &[1, 0, 3, 0]), vec![1, 3]);
+        assert_eq!(diff_offsets(&[1, 2], &[0, 2, 3, 4]), vec![0, 2, 3]);
+        assert_eq!(diff_offsets(&[1, 2, 3], &[1]), vec![1, 2]);
+
+        // Embedding leaves everything ahead of the first DHT segment alone
+        let input = TestImage::new(48, 32).build();
+        let mut output = Vec::new();
+        write_secret(
+            &mut Cursor::new(&input),
+            &mut output,
+            b"secret",
+            &WriteOptions::default(),
+        )
+        .unwrap();
+
+        let first_dht = input
+            .windows(2)
+            .position(|bytes| bytes == [0xFF, 0xC4])
+            .unwrap();
+        let offsets = diff_offsets(&input, &output);
+        assert!(!offsets.is_empty());
+        assert!(offsets.iter().all(|&offset| offset > first_dht));
+    }
+}
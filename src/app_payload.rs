@@ -0,0 +1,146 @@
+// A plainer channel than the Huffman tables: bytes stored as they are in application segments,
+// which decoders skip over. Anyone looking at the file's segments can see them, but they're kept
+// by tools that copy application data through untouched.
+
+use std::io::{Read, Write};
+
+use anyhow::{bail, Result};
+
+use crate::jpeg::{Jpeg, Marker, Segment};
+
+const PAYLOAD_HEADER: [u8; 2] = [0xBE, 0xEF];
+
+// A segment's length field counts itself, leaving 65533 bytes, less the header
+const MAX_CHUNK_SIZE: usize = 0xFFFF - 2 - PAYLOAD_HEADER.len();
+
+/// Writes the file out with `payload` stored in APPn segments (n being `app_index`), placed just
+/// after APP0. Payloads too large for one segment are split across several. Any payload already
+/// in APPn segments is replaced.
+pub fn write_app_payload<W: Write>(
+    jpeg: &mut Jpeg,
+    writer: &mut W,
+    app_index: u8,
+    payload: &[u8],
+) -> Result<()> {
+    let marker = app_marker(app_index)?;
+    jpeg.segments
+        .retain(|segment| !(segment.marker == marker && is_payload(segment)));
+
+    let position = jpeg
+        .segments
+        .iter()
+        .position(|segment| segment.marker == Marker::APP(0))
+        .map(|position| position + 1)
+        .unwrap_or(1); // Just after SOI
+    let index = jpeg
+        .segments
+        .get(position)
+        .map(|segment| segment.index)
+        .unwrap_or_default();
+
+    let mut chunks = payload.chunks(MAX_CHUNK_SIZE).collect::<Vec<_>>();
+    if chunks.is_empty() {
+        chunks.push(&[]);
+    }
+
+    let segments = chunks.into_iter().map(|chunk| {
+        let mut data = PAYLOAD_HEADER.to_vec();
+        data.extend(chunk);
+        Segment {
+            index,
+            marker,
+            data,
+        }
+    });
+    jpeg.segments.splice(position..position, segments);
+
+    jpeg.write_to(writer)
+}
+
+/// The payload `write_app_payload` stored in the file's APPn segments, if there is one
+pub fn read_app_payload<R: Read>(reader: &mut R, app_index: u8) -> Result<Option<Vec<u8>>> {
+    let marker = app_marker(app_index)?;
+    let jpeg = Jpeg::read_headers_only(reader)?;
+
+    let mut chunks = jpeg
+        .segments
+        .iter()
+        .filter(|segment| segment.marker == marker && is_payload(segment))
+        .peekable();
+    if chunks.peek().is_none() {
+        return Ok(None);
+    }
+
+    Ok(Some(
+        chunks
+            .flat_map(|segment| &segment.data[PAYLOAD_HEADER.len()..])
+            .copied()
+            .collect(),
+    ))
+}
+
+fn app_marker(app_index: u8) -> Result<Marker> {
+    if app_index > 15 {
+        bail!("There is no APP{app_index} marker; application segments run from APP0 to APP15");
+    }
+    Ok(Marker::APP(app_index))
+}
+
+fn is_payload(segment: &Segment) -> bool {
+    segment.data.starts_with(&PAYLOAD_HEADER)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::jpeg::test_image::{decode_pixels, TestImage};
+
+    fn write(input: &[u8], app_index: u8, payload: &[u8]) -> Result<Vec<u8>> {
+        let mut jpeg = Jpeg::read_segments(&mut Cursor::new(input))?;
+        let mut output = Vec::new();
+        write_app_payload(&mut jpeg, &mut output, app_index, payload)?;
+        Ok(output)
+    }
+
+    fn read(input: &[u8], app_index: u8) -> Option<Vec<u8>> {
+        read_app_payload(&mut Cursor::new(input), app_index).unwrap()
+    }
+
+    #[test]
+    fn test_app_payload() {
+        let input = TestImage::new(48, 32).build();
+        assert_eq!(read(&input, 11), None);
+
+        let output = write(&input, 11, b"payload").unwrap();
+        assert_eq!(read(&output, 11), Some(b"payload".to_vec()));
+        assert_eq!(read(&output, 12), None);
+        assert_eq!(decode_pixels(&output), decode_pixels(&input));
+
+        let jpeg = Jpeg::read_segments(&mut Cursor::new(&output)).unwrap();
+        let markers = jpeg.segments.iter().map(|s| s.marker).collect::<Vec<_>>();
+        assert_eq!(markers[1..3], [Marker::APP(0), Marker::APP(11)]);
+
+        // Writing again replaces the payload
+        let output = write(&output, 11, b"").unwrap();
+        assert_eq!(read(&output, 11), Some(Vec::new()));
+
+        assert!(write(&input, 16, b"payload").is_err());
+    }
+
+    #[test]
+    fn test_app_payload_chunks() {
+        let input = TestImage::new(48, 32).build();
+        for size in [MAX_CHUNK_SIZE, MAX_CHUNK_SIZE + 1, 3 * MAX_CHUNK_SIZE - 7] {
+            let payload = (0..size).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+            let output = write(&input, 5, &payload).unwrap();
+            assert_eq!(read(&output, 5), Some(payload));
+            assert_eq!(decode_pixels(&output), decode_pixels(&input));
+
+            let jpeg = Jpeg::read_segments(&mut Cursor::new(&output)).unwrap();
+            let chunks = jpeg.segments.iter().filter(|s| s.marker == Marker::APP(5));
+            assert_eq!(chunks.count(), size.div_ceil(MAX_CHUNK_SIZE));
+        }
+    }
+}
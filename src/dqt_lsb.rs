@@ -0,0 +1,193 @@
+// A channel independent of the Huffman tables: the lowest bit of each quantization table value.
+// The file keeps its tables and decodes as before, but each value moved by one rescales every
+// coefficient it applies to by 1/q, so the image shifts slightly -- most at the small values of
+// the low frequencies, least at the large ones of the high frequencies. The scans aren't
+// re-encoded to make up for it.
+
+use std::io::{Read, Write};
+
+use anyhow::Result;
+
+use crate::{
+    jpeg::{
+        segments::{DqtData, ToVec},
+        Jpeg, Marker,
+    },
+    StegError,
+};
+
+const PAYLOAD_HEADER: [u8; 2] = [0xBE, 0xEF];
+
+// The header, then the secret's length as 16 bits
+const FRAME_LEN: usize = PAYLOAD_HEADER.len() + 2;
+
+/// Writes the file out with `secret` in the lowest bits of its quantization table values, taken
+/// in file order, most significant bit first. Values of 1 carry nothing and are left alone, as
+/// clearing their lowest bit would leave a quantizer of 0; every other value stays 2 or more.
+/// The scans are copied through as they are.
+pub fn write_secret_dqt_lsb<R: Read, W: Write, T: AsRef<[u8]>>(
+    reader: &mut R,
+    writer: &mut W,
+    secret: T,
+) -> Result<()> {
+    let secret = secret.as_ref();
+    let mut jpeg = Jpeg::read_segments(reader)?;
+
+    let capacity = carrier_values(&jpeg)? / 8;
+    let length = match u16::try_from(secret.len()) {
+        Ok(length) if FRAME_LEN + secret.len() <= capacity => length,
+        _ => {
+            return Err(StegError::PayloadTooLarge {
+                attempted: secret.len(),
+                max: capacity.saturating_sub(FRAME_LEN),
+            }
+            .into())
+        }
+    };
+
+    let mut payload = PAYLOAD_HEADER.to_vec();
+    payload.extend(length.to_be_bytes());
+    payload.extend(secret);
+    let mut bits = payload
+        .iter()
+        .flat_map(|byte| (0..8).rev().map(move |shift| (byte >> shift) as u16 & 1));
+
+    for segment in jpeg.segments.iter_mut().filter(|s| s.marker == Marker::DQT) {
+        let mut dqt = DqtData::try_from(&segment.data[..])?;
+        let values = dqt.tables.iter_mut().flat_map(|table| &mut table.values);
+        for (value, bit) in values.filter(|value| **value > 1).zip(&mut bits) {
+            *value = (*value & !1) | bit;
+        }
+        segment.data = dqt.to_vec();
+    }
+
+    jpeg.write_to(writer)
+}
+
+/// The secret `write_secret_dqt_lsb` stored in the file's quantization tables, if there is one
+pub fn read_secret_dqt_lsb<R: Read>(reader: &mut R) -> Result<Option<Vec<u8>>> {
+    let jpeg = Jpeg::read_headers_only(reader)?;
+
+    let mut bits = Vec::new();
+    for segment in jpeg.segments.iter().filter(|s| s.marker == Marker::DQT) {
+        for table in DqtData::try_from(&segment.data[..])?.tables {
+            bits.extend(table.values.into_iter().filter(|&value| value > 1));
+        }
+    }
+    let bytes = bits
+        .chunks_exact(8)
+        .map(|bits| {
+            bits.iter()
+                .fold(0u8, |byte, bit| (byte << 1) | (bit & 1) as u8)
+        })
+        .collect::<Vec<_>>();
+
+    if !bytes.starts_with(&PAYLOAD_HEADER) || bytes.len() < FRAME_LEN {
+        return Ok(None);
+    }
+    let length = u16::from_be_bytes([bytes[2], bytes[3]]) as usize;
+    Ok(bytes.get(FRAME_LEN..FRAME_LEN + length).map(<[u8]>::to_vec))
+}
+
+/// How many of the file's quantization table values can carry a bit
+fn carrier_values(jpeg: &Jpeg) -> Result<usize> {
+    let mut count = 0;
+    for segment in jpeg.segments.iter().filter(|s| s.marker == Marker::DQT) {
+        for table in DqtData::try_from(&segment.data[..])?.tables {
+            count += table.values.iter().filter(|&&value| value > 1).count();
+        }
+    }
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::jpeg::test_image::{decode_pixels, TestImage};
+    use crate::{read_secret, write_secret, WriteOptions};
+
+    fn write(input: &[u8], secret: &[u8]) -> Result<Vec<u8>> {
+        let mut output = Vec::new();
+        write_secret_dqt_lsb(&mut Cursor::new(input), &mut output, secret)?;
+        Ok(output)
+    }
+
+    fn read(input: &[u8]) -> Option<Vec<u8>> {
+        read_secret_dqt_lsb(&mut Cursor::new(input)).unwrap()
+    }
+
+    fn quantizers(data: &[u8]) -> Vec<u16> {
+        let jpeg = Jpeg::read_segments(&mut Cursor::new(data)).unwrap();
+        jpeg.segments
+            .iter()
+            .filter(|s| s.marker == Marker::DQT)
+            .flat_map(|s| DqtData::try_from(&s.data[..]).unwrap().tables)
+            .flat_map(|table| table.values)
+            .collect()
+    }
+
+    #[test]
+    fn test_dqt_lsb() {
+        let input = include_bytes!("../docs/dove-small-in.jpg");
+        assert_eq!(read(input), None);
+
+        let output = write(input, b"secret").unwrap();
+        assert_eq!(read(&output), Some(b"secret".to_vec()));
+        assert_eq!(output.len(), input.len());
+        assert_eq!(
+            write(input, b"").map(|output| read(&output)).unwrap(),
+            Some(vec![])
+        );
+
+        // Each quantizer moves by at most one, and none reaches 0; the image still decodes
+        let (before, after) = (quantizers(input), quantizers(&output));
+        assert_ne!(before, after);
+        assert!(before
+            .iter()
+            .zip(&after)
+            .all(|(&a, &b)| b > 0 && a.abs_diff(b) <= 1 && (a > 1 || a == b)));
+        assert_eq!(decode_pixels(&output).len(), decode_pixels(input).len());
+
+        // Independent of the secret carried by the Huffman tables
+        let mut both = Vec::new();
+        write_secret(
+            &mut Cursor::new(&output),
+            &mut both,
+            b"tables",
+            &WriteOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(read(&both), Some(b"secret".to_vec()));
+        let tables = read_secret(&mut Cursor::new(&both)).unwrap();
+        assert_eq!(tables, Some(b"tables".to_vec()));
+
+        let max = before.iter().filter(|&&q| q > 1).count() / 8 - FRAME_LEN;
+        assert!(write(input, &vec![0xAA; max]).is_ok());
+        let error = write(input, &vec![0xAA; max + 1]).unwrap_err();
+        assert_eq!(
+            error.downcast_ref(),
+            Some(&StegError::PayloadTooLarge {
+                attempted: max + 1,
+                max
+            })
+        );
+    }
+
+    #[test]
+    fn test_dqt_lsb_all_ones() {
+        // Quantizers of 1 can't give up their lowest bit, so there's no room at all
+        let input = TestImage::new(48, 32).build();
+        assert!(quantizers(&input).iter().all(|&q| q == 1));
+        let error = write(&input, b"").unwrap_err();
+        assert_eq!(
+            error.downcast_ref(),
+            Some(&StegError::PayloadTooLarge {
+                attempted: 0,
+                max: 0
+            })
+        );
+        assert_eq!(read(&input), None);
+    }
+}
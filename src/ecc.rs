@@ -0,0 +1,302 @@
+//! Reed-Solomon erasure coding, so a secret can be recovered with some of its shards lost. The
+//! code is systematic: the data shards are the secret itself, split evenly, and the parity shards
+//! come from `reed-solomon-erasure`, which makes any `data_shards` of the shards enough to solve
+//! for the rest. Each shard is framed with a CRC-32, so a damaged one is dropped as lost.
+
+use reed_solomon_erasure::galois_8::ReedSolomon;
+
+const SHARD_HEADER: [u8; 2] = [0xBE, 0xF1];
+/// The header, the shard's index, the shard counts and the secret's length, then a CRC-32
+pub(crate) const SHARD_OVERHEAD: usize = SHARD_HEADER.len() + 3 + 4 + 4;
+
+/// How to split the secret for `WriteOptions::ecc`. Each of the `data_shards + parity_shards`
+/// shards goes in its own group of tables, and any `parity_shards` of the groups can be lost.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EccParams {
+    pub data_shards: usize,
+    pub parity_shards: usize,
+}
+
+impl EccParams {
+    pub fn total_shards(&self) -> usize {
+        self.data_shards + self.parity_shards
+    }
+}
+
+/// The secret split into shards with parity, each framed so a damaged one can be told apart
+pub(crate) fn shard_records(secret: &[u8], params: &EccParams) -> anyhow::Result<Vec<Vec<u8>>> {
+    let EccParams {
+        data_shards,
+        parity_shards,
+    } = *params;
+    if data_shards == 0 || params.total_shards() > 255 {
+        anyhow::bail!(
+            "Can't split a secret into {data_shards} data and {parity_shards} parity shards; there must be at least one data shard and at most 255 shards"
+        );
+    }
+
+    let shard_len = secret.len().div_ceil(data_shards);
+    let mut shards = (0..data_shards)
+        .map(|index| {
+            let mut shard = secret
+                .iter()
+                .skip(index * shard_len)
+                .take(shard_len)
+                .copied();
+            (0..shard_len)
+                .map(|_| shard.next().unwrap_or(0))
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+
+    // There's nothing to code without parity, and the coder won't take the empty shards of an
+    // empty secret
+    shards.resize(params.total_shards(), vec![0; shard_len]);
+    if parity_shards > 0 && shard_len > 0 {
+        ReedSolomon::new(data_shards, parity_shards)?.encode(&mut shards)?;
+    }
+
+    Ok(shards
+        .into_iter()
+        .enumerate()
+        .map(|(index, shard)| {
+            let mut record = SHARD_HEADER.to_vec();
+            record.extend([index as u8, data_shards as u8, parity_shards as u8]);
+            record.extend((secret.len() as u32).to_be_bytes());
+            record.extend(shard);
+            record.extend(crc32fast::hash(&record).to_be_bytes());
+            record
+        })
+        .collect())
+}
+
+/// The secret, from whichever of the `total_shards` records survived intact, if there are enough
+pub(crate) fn recover<I: IntoIterator<Item = Vec<u8>>>(
+    records: I,
+    total_shards: usize,
+) -> Option<Vec<u8>> {
+    let shards = records
+        .into_iter()
+        .filter_map(parse_record)
+        .filter(|shard| shard.params.total_shards() == total_shards)
+        .collect::<Vec<_>>();
+
+    // Every intact shard agrees on how the secret was split, so the first will do. A CRC only
+    // catches damage, though, so one made to pass it is dropped unless it agrees in full.
+    let first = shards.first()?;
+    let (params, secret_len, shard_len) = (first.params, first.secret_len, first.data.len());
+    let data_shards = params.data_shards;
+
+    let mut available = vec![None; total_shards];
+    for shard in shards {
+        if shard.params == params && shard.secret_len == secret_len && shard.data.len() == shard_len
+        {
+            available[shard.index].get_or_insert(shard.data);
+        }
+    }
+
+    if available.iter().flatten().count() < data_shards {
+        return None;
+    }
+    if shard_len == 0 {
+        return (secret_len == 0).then(Vec::new);
+    }
+    if params.parity_shards > 0 {
+        let coder = ReedSolomon::new(data_shards, params.parity_shards).ok()?;
+        coder.reconstruct_data(&mut available).ok()?;
+    }
+
+    let mut secret = available
+        .into_iter()
+        .take(data_shards)
+        .collect::<Option<Vec<_>>>()?
+        .concat();
+    if secret.len() < secret_len {
+        return None;
+    }
+    secret.truncate(secret_len);
+    Some(secret)
+}
+
+/// The secret as its data shards stand, for inspecting one `recover` can't restore. Checksums
+/// are ignored, and any data shard that's missing entirely is left as zeros.
+pub(crate) fn recover_unchecked<I: IntoIterator<Item = Vec<u8>>>(
+    records: I,
+    total_shards: usize,
+) -> Option<Vec<u8>> {
+    let shards = records
+        .into_iter()
+        .filter_map(|record| parse_body(&record[..record.len().saturating_sub(4)]))
+        .filter(|shard| shard.params.total_shards() == total_shards)
+        .collect::<Vec<_>>();
+
+    let first = shards.first()?;
+    let (params, secret_len, shard_len) = (first.params, first.secret_len, first.data.len());
+
+    let mut secret = vec![0; params.data_shards * shard_len];
+    for shard in &shards {
+        if shard.params == params
+            && shard.index < params.data_shards
+            && shard.data.len() == shard_len
+        {
+            secret[shard.index * shard_len..][..shard_len].copy_from_slice(&shard.data);
+        }
+    }
+    secret.truncate(secret_len);
+    Some(secret)
+}
+
+struct Shard {
+    index: usize,
+    params: EccParams,
+    secret_len: usize,
+    data: Vec<u8>,
+}
+
+/// Whether `record` starts as a shard's does, without checking any more of it
+pub(crate) fn has_shard_header(record: &[u8]) -> bool {
+    record.starts_with(&SHARD_HEADER)
+}
+
+fn parse_record(record: Vec<u8>) -> Option<Shard> {
+    if record.len() < SHARD_OVERHEAD {
+        return None;
+    }
+
+    let (body, crc) = record.split_at(record.len() - 4);
+    if crc32fast::hash(body).to_be_bytes() != crc {
+        return None;
+    }
+    parse_body(body)
+}
+
+fn parse_body(body: &[u8]) -> Option<Shard> {
+    if body.len() < SHARD_OVERHEAD - 4 || !body.starts_with(&SHARD_HEADER) {
+        return None;
+    }
+
+    let params = EccParams {
+        data_shards: body[3] as usize,
+        parity_shards: body[4] as usize,
+    };
+    let index = body[2] as usize;
+    if params.data_shards == 0 || index >= params.total_shards() {
+        return None;
+    }
+
+    Some(Shard {
+        index,
+        params,
+        secret_len: u32::from_be_bytes(body[5..9].try_into().unwrap()) as usize,
+        data: body[9..].to_vec(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_recover() {
+        let params = EccParams {
+            data_shards: 3,
+            parity_shards: 2,
+        };
+        let secret = b"a secret split five ways".to_vec();
+        let records = shard_records(&secret, &params).unwrap();
+        assert_eq!(records.len(), 5);
+
+        assert_eq!(recover(records.clone(), 5), Some(secret.clone()));
+
+        // Any two may be lost, but not three
+        for lost in [[0, 1], [0, 4], [2, 3], [3, 4]] {
+            let surviving = records
+                .iter()
+                .enumerate()
+                .filter(|(index, _)| !lost.contains(index))
+                .map(|(_, record)| record.clone());
+            assert_eq!(recover(surviving, 5), Some(secret.clone()));
+        }
+        assert_eq!(recover(records[3..].to_vec(), 5), None);
+
+        // A damaged shard counts as lost
+        let mut damaged = records.clone();
+        damaged[0][10] ^= 1;
+        damaged[1].truncate(8);
+        assert_eq!(recover(damaged.clone(), 5), Some(secret.clone()));
+        damaged[2][12] ^= 1;
+        assert_eq!(recover(damaged, 5), None);
+
+        assert_eq!(recover(records, 4), None);
+    }
+
+    #[test]
+    fn test_recover_unequal_shards() {
+        let params = EccParams {
+            data_shards: 3,
+            parity_shards: 2,
+        };
+        let secret = b"a secret split five ways".to_vec();
+        let records = shard_records(&secret, &params).unwrap();
+
+        // A shard that passes its CRC but is longer than the rest, as a crafted carrier could hold
+        let lengthen = |record: &[u8]| {
+            let mut record = record[..record.len() - 4].to_vec();
+            record.push(0);
+            record.extend(crc32fast::hash(&record).to_be_bytes());
+            record
+        };
+
+        let mut crafted = records.clone();
+        crafted[1] = lengthen(&records[1]);
+        assert_eq!(recover(crafted, 5), Some(secret));
+
+        // Taken as the first, it leaves too few shards that agree with it
+        let mut crafted = records.clone();
+        crafted[0] = lengthen(&records[0]);
+        assert_eq!(recover(crafted, 5), None);
+    }
+
+    #[test]
+    fn test_recover_unchecked() {
+        let params = EccParams {
+            data_shards: 3,
+            parity_shards: 1,
+        };
+        let secret = b"a damaged secret".to_vec();
+        let mut records = shard_records(&secret, &params).unwrap();
+        assert_eq!(recover_unchecked(records.clone(), 4), Some(secret.clone()));
+
+        // Too damaged to recover, but what's left of the data still shows through
+        records[0][12] ^= 1;
+        records.remove(2);
+        assert_eq!(recover(records.clone(), 4), None);
+        let damaged = recover_unchecked(records.clone(), 4).unwrap();
+        assert_eq!(damaged.len(), secret.len());
+        assert_eq!(&damaged[..3], &secret[..3]);
+        assert_ne!(damaged[3], secret[3]);
+        assert_eq!(&damaged[4..12], &secret[4..12]);
+        assert_eq!(&damaged[12..], [0; 4]);
+
+        assert_eq!(recover_unchecked(records, 5), None);
+    }
+
+    #[test]
+    fn test_params() {
+        let records = |data_shards, parity_shards| {
+            let params = EccParams {
+                data_shards,
+                parity_shards,
+            };
+            shard_records(b"secret", &params)
+        };
+
+        assert!(records(0, 2).is_err());
+        assert!(records(200, 56).is_err());
+        assert_eq!(records(1, 0).unwrap().len(), 1);
+
+        // More data shards than bytes leaves some empty
+        let records = records(8, 1).unwrap();
+        assert_eq!(recover(records[1..].to_vec(), 9), Some(b"secret".to_vec()));
+    }
+}
@@ -0,0 +1,50 @@
+use std::fmt;
+
+/// Failures worth telling apart from the rest. These travel inside `anyhow::Error`, so match on
+/// them with `error.downcast_ref::<StegError>()`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StegError {
+    /// The `should_continue` hook on `Jpeg` asked for processing to stop
+    Cancelled,
+    /// The input doesn't start with an SOI marker, so isn't a JPEG file
+    NotAJpeg,
+    /// The output buffer given to `write_secret_to_slice` can't hold the whole file
+    BufferTooSmall,
+    /// Embedding was asked of a progressive file, which isn't supported
+    Progressive,
+    /// The secret is longer than the image's tables can hold. `max` is roughly the longest that
+    /// would fit, in bytes.
+    PayloadTooLarge { attempted: usize, max: usize },
+    /// The entropy decoder didn't finish within a byte of the end of a scan's data: it stopped
+    /// `read_bits` into its `total_bits`, or ran out of data there
+    EntropyDesync { read_bits: u64, total_bits: u64 },
+    /// The image holds fewer bytes than `WriteOptions::min_capacity` asks for
+    LowCapacity { capacity: usize },
+}
+
+impl fmt::Display for StegError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StegError::Cancelled => write!(f, "Processing was cancelled"),
+            StegError::NotAJpeg => write!(f, "Input isn't a JPEG file"),
+            StegError::BufferTooSmall => write!(f, "Output buffer is too small"),
+            StegError::Progressive => write!(f, "Progressive JPEG files not supported"),
+            StegError::PayloadTooLarge { attempted, max } => write!(
+                f,
+                "Couldn't fit secret into image: {attempted} bytes given, but at most {max} fit"
+            ),
+            StegError::EntropyDesync {
+                read_bits,
+                total_bits,
+            } => write!(
+                f,
+                "Entropy decoding lost sync: stopped at bit {read_bits} of {total_bits} in the scan data"
+            ),
+            StegError::LowCapacity { capacity } => {
+                write!(f, "Image can only hold {capacity} bytes of secret")
+            }
+        }
+    }
+}
+
+impl std::error::Error for StegError {}
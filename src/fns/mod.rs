@@ -20,3 +20,179 @@ fn factorial(mut n: usize) -> num_bigint::BigUint {
 
     result
 }
+
+/// Splits a table's `values` into the runs of each code length, given the table's count of codes
+/// of each length. Lengths without any codes are skipped, rather than given an empty run. Panics
+/// if `values` holds fewer than the counts add up to.
+pub fn split_table_values<'a>(sizes: &[usize], mut values: &'a [u8]) -> Vec<&'a [u8]> {
+    let mut results = Vec::new();
+    for &size in sizes.iter().filter(|&&size| size > 0) {
+        let (local_values, next_values) = values.split_at(size);
+        values = next_values;
+        results.push(local_values);
+    }
+    results
+}
+
+/// As `split_table_values`, for re-arranging the values in place
+pub fn split_table_values_mut<'a>(sizes: &[usize], mut values: &'a mut [u8]) -> Vec<&'a mut [u8]> {
+    let mut results = Vec::new();
+    for &size in sizes.iter().filter(|&&size| size > 0) {
+        let (local_values, next_values) = values.split_at_mut(size);
+        values = next_values;
+        results.push(local_values);
+    }
+    results
+}
+
+/// The values each table is written with to carry `encoded`, exactly as `write_secret_biguint`
+/// writes them, for checking another implementation against. `sizes` holds each table's 16 counts
+/// of codes of each length, one table after another, and `values` the tables' values likewise.
+///
+/// `encoded` is taken as a mixed-radix number, most significant digit first at every level:
+/// - Each table with any codes is a digit, in the order given. Its base is the product of the
+///   factorials of its counts. Tables without codes are left out.
+/// - Within a table, each code length with codes is a digit, shortest first, with a base of the
+///   factorial of its count.
+/// - Within a code length of `n` values, the digit is a Lehmer code: `n - 1` digits with bases
+///   `(n - 1)!` down to `1!`. Each picks, by its index, which of the values not yet placed (in
+///   ascending order) goes in the next position; the last position takes the one left over.
+///
+/// Each code length's values are sorted ascending before anything is placed, so only which values
+/// share a length matters, not their order, and an `encoded` of 0 leaves every length sorted.
+/// Fails if the counts don't come in 16s or don't add up to the number of values, or if `encoded`
+/// is too large for the tables to hold.
+pub fn apply_permutation(
+    sizes: &[usize],
+    values: &[u8],
+    encoded: &num_bigint::BigUint,
+) -> anyhow::Result<Vec<Vec<u8>>> {
+    let tables = sizes.chunks_exact(16);
+    if !tables.remainder().is_empty() {
+        anyhow::bail!("Expected 16 code counts per table, but got {}", sizes.len());
+    }
+    let table_sizes = tables
+        .map(|counts| counts.iter().map(|&count| u8::try_from(count)).collect())
+        .collect::<Result<Vec<Vec<u8>>, _>>()
+        .map_err(|_| anyhow::anyhow!("A code length has more codes than a table can hold"))?;
+
+    let total = sizes.iter().sum::<usize>();
+    if total != values.len() {
+        anyhow::bail!(
+            "The counts add up to {total} values, but got {}",
+            values.len()
+        );
+    }
+
+    let mut values = values;
+    let mut table_values = Vec::new();
+    for counts in &table_sizes {
+        let (table, rest) = values.split_at(counts.iter().map(|&count| count as usize).sum());
+        table_values.push(table.to_vec());
+        values = rest;
+    }
+
+    let ns = match NS2::try_from_input(encoded.clone(), &table_sizes) {
+        None => anyhow::bail!(
+            "Value doesn't fit in the tables, which hold values below {}",
+            table_sizes.max_base_value()
+        ),
+        Some(ns) => ns,
+    };
+    ns.permute_values(&mut table_values);
+    Ok(table_values)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_table_values() {
+        let mut values = [1, 2, 3, 4, 5, 6];
+        let sizes = [0, 2, 0, 1, 3];
+        assert_eq!(
+            split_table_values(&sizes, &values),
+            [&[1, 2][..], &[3], &[4, 5, 6]]
+        );
+
+        let mut runs = split_table_values_mut(&sizes, &mut values);
+        runs[2].reverse();
+        assert_eq!(values, [1, 2, 3, 6, 5, 4]);
+
+        // Values past the counts are left out
+        assert_eq!(split_table_values(&[1], &[7, 8]), [&[7][..]]);
+    }
+
+    #[test]
+    fn test_apply_permutation() {
+        use num_bigint::BigUint;
+
+        let counts = |lengths: &[usize]| {
+            let mut sizes = lengths.to_vec();
+            sizes.resize(16, 0);
+            sizes
+        };
+        let apply = |sizes: &[usize], values: &[u8], encoded: u32| {
+            apply_permutation(sizes, values, &BigUint::from(encoded)).unwrap()
+        };
+
+        // One length of three values: the Lehmer code with bases 2! and 1!
+        let sizes = counts(&[0, 3]);
+        let orders = (0..6)
+            .map(|encoded| apply(&sizes, &[30, 10, 20], encoded).remove(0))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            orders,
+            [
+                [10, 20, 30],
+                [10, 30, 20],
+                [20, 10, 30],
+                [20, 30, 10],
+                [30, 10, 20],
+                [30, 20, 10]
+            ]
+        );
+
+        // Shorter lengths, then earlier tables, are more significant
+        let mut sizes = counts(&[2, 2]);
+        sizes.extend(counts(&[]));
+        sizes.extend(counts(&[0, 0, 2]));
+        let values = [1, 2, 3, 4, 5, 6];
+        assert_eq!(
+            apply(&sizes, &values, 0),
+            [vec![1, 2, 3, 4], vec![], vec![5, 6]]
+        );
+        assert_eq!(
+            apply(&sizes, &values, 1),
+            [vec![1, 2, 3, 4], vec![], vec![6, 5]]
+        );
+        assert_eq!(
+            apply(&sizes, &values, 2),
+            [vec![1, 2, 4, 3], vec![], vec![5, 6]]
+        );
+        assert_eq!(
+            apply(&sizes, &values, 4),
+            [vec![2, 1, 3, 4], vec![], vec![5, 6]]
+        );
+        assert_eq!(
+            apply(&sizes, &values, 7),
+            [vec![2, 1, 4, 3], vec![], vec![6, 5]]
+        );
+
+        // 2! * 2! * 2! values fit, and no more
+        let encoded = BigUint::from(8u8);
+        assert!(apply_permutation(&sizes, &values, &encoded).is_err());
+        assert!(apply_permutation(&sizes, &values[1..], &BigUint::from(0u8)).is_err());
+        assert!(apply_permutation(&sizes[1..], &values[1..], &BigUint::from(0u8)).is_err());
+
+        // It's what NS2 reads back
+        let table_values = apply(&sizes, &values, 5);
+        let table_sizes = sizes
+            .chunks(16)
+            .map(|counts| counts.iter().map(|&count| count as u8).collect())
+            .collect();
+        let read = NS2::read_value(&table_sizes, &table_values);
+        assert_eq!(read, BigUint::from(5u8));
+    }
+}
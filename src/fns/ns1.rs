@@ -2,6 +2,7 @@ use num_bigint::BigUint;
 use num_traits::Zero;
 
 use super::{
+    split_table_values, split_table_values_mut,
     traits::{DigitsBases, TryFromInput, ValidInputs},
     NS0,
 };
@@ -20,18 +21,34 @@ impl NS1 {
         }
     }
 
-    fn split_values_mut<'a>(&self, mut values: &'a mut [u8]) -> Vec<&'a mut [u8]> {
-        let mut results = Vec::new();
+    /// The position in sorted order of the value each position holds, with each code length's
+    /// values indexed after those of the shorter lengths
+    pub fn to_permutation(&self) -> Vec<usize> {
+        let mut offset = 0;
+        let mut permutation = Vec::new();
         for digit in &self.digits {
-            let size = digit.digits.len() + 1;
-            let (local_values, next_values) = values.split_at_mut(size);
-            values = next_values;
-            results.push(local_values);
+            let local = digit.to_permutation();
+            permutation.extend(local.iter().map(|index| offset + index));
+            offset += local.len();
         }
-        results
+        permutation
+    }
+
+    fn split_values_mut<'a>(&self, values: &'a mut [u8]) -> Vec<&'a mut [u8]> {
+        let sizes = self
+            .digits
+            .iter()
+            .map(|digit| digit.digits.len() + 1)
+            .collect::<Vec<_>>();
+        split_table_values_mut(&sizes, values)
     }
 
     pub fn read_values(input: &Vec<u8>, values: &[u8]) -> Self {
+        NS1::try_from_input(Self::read_value(input, values), input).unwrap()
+    }
+
+    /// The number `read_values` stands for, without splitting it back into digits
+    pub fn read_value(input: &Vec<u8>, values: &[u8]) -> BigUint {
         let mut result = BigUint::zero();
         let values = Self::split_values(input, values);
         for (base, values) in super::traits::get_bases(&input.valid())
@@ -41,17 +58,12 @@ impl NS1 {
             let value = BigUint::from(NS0::read_values(values));
             result += base * value;
         }
-        NS1::try_from_input(result, input).unwrap()
+        result
     }
 
-    fn split_values<'a>(sizes: &Input, mut values: &'a [u8]) -> Vec<&'a [u8]> {
-        let mut results = Vec::new();
-        for size in sizes.valid() {
-            let (local_values, next_values) = values.split_at(size as usize);
-            values = next_values;
-            results.push(local_values);
-        }
-        results
+    fn split_values<'a>(sizes: &Input, values: &'a [u8]) -> Vec<&'a [u8]> {
+        let sizes = sizes.iter().map(|&size| size as usize).collect::<Vec<_>>();
+        split_table_values(&sizes, values)
     }
 }
 
@@ -136,6 +148,17 @@ mod tests {
         assert_eq!(buf, vec![3, 10, 5, 25, 15, 20]);
     }
 
+    #[test]
+    fn test_to_permutation() {
+        let input = vec![3, 3];
+        assert_eq!(n(0, &input).unwrap().to_permutation(), [0, 1, 2, 3, 4, 5]);
+        assert_eq!(n(10, &input).unwrap().to_permutation(), [0, 2, 1, 5, 3, 4]);
+
+        let buf = vec![3, 10, 5, 25, 15, 20];
+        let ns = NS1::read_values(&vec![0, 3, 0, 3], &buf);
+        assert_eq!(ns.to_permutation(), [0, 2, 1, 5, 3, 4]);
+    }
+
     #[test]
     fn test_read_values() {
         let buf = vec![25, 15, 5, 0, 20, 10];
@@ -148,6 +171,22 @@ mod tests {
         assert_eq!(big(NS1::read_values(&vec![6, 0], &buf)), 679);
     }
 
+    #[test]
+    fn test_no_capacity() {
+        // No length has two values to swap, so zero is the only value the table can hold
+        for (input, values) in [(vec![0, 0], vec![]), (vec![1, 0, 1, 0], vec![4, 2])] {
+            let ns = NS1::read_values(&input, &values);
+            assert_eq!(big(ns.clone()), 0);
+            assert_eq!(n(0, &input).unwrap().inner_digits(), ns.inner_digits());
+            assert!(n(1, &input).is_none());
+
+            let mut permuted = values.clone();
+            ns.permute_values(&mut permuted);
+            assert_eq!(permuted, values);
+            assert_eq!(ns.to_permutation(), (0..values.len()).collect::<Vec<_>>());
+        }
+    }
+
     #[test]
     fn test_case_failure1() {
         let input = [0, 1, 5, 1, 1, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0];
@@ -2,7 +2,7 @@ use num_bigint::BigUint;
 use num_traits::Zero;
 
 use super::{
-    traits::{DigitsBases, TryFromInput, ValidInputs},
+    traits::{DigitsBases, MaxBaseValue, TryFromInput, ValidInputs},
     NS1,
 };
 
@@ -14,22 +14,45 @@ impl_sub_ns!(NS2, Input, InnerDigit);
 
 impl NS2 {
     pub fn permute_values(&self, values: &mut Input) {
+        // A table without any codes has no digit, so the others are paired up past it
+        let values = values.iter_mut().filter(|values| !values.is_empty());
         for (digit, values) in self.digits.iter().zip(values) {
             digit.permute_values(values);
         }
     }
 
+    /// The tables' indices from least to most capacity, ties keeping their order. Giving the
+    /// most significant digits to the first of these leaves the smaller tables unchanged by all
+    /// but the largest values.
+    pub fn least_changed_order(input: &Input) -> Vec<usize> {
+        let mut order = (0..input.len()).collect::<Vec<_>>();
+        order.sort_by_cached_key(|&index| input[index].max_base_value());
+        order
+    }
+
+    /// Each table's `NS1::to_permutation`
+    pub fn to_permutations(&self) -> Vec<Vec<usize>> {
+        self.digits
+            .iter()
+            .map(|digit| digit.to_permutation())
+            .collect()
+    }
+
     pub fn read_values(input: &Input, values: &Input) -> Self {
+        NS2::try_from_input(Self::read_value(input, values), input).unwrap()
+    }
+
+    /// The number `read_values` stands for, without splitting it back into digits
+    pub fn read_value(input: &Input, values: &Input) -> BigUint {
         let mut result = BigUint::zero();
-        for (input, (base, values)) in input.iter().zip(
-            super::traits::get_bases(&input.valid())
-                .into_iter()
-                .zip(values),
-        ) {
-            let value = BigUint::from(NS1::read_values(input, values));
-            result += base * value;
+        let tables = input
+            .iter()
+            .zip(values)
+            .filter(|(input, _)| !input.valid().is_empty());
+        for ((input, values), base) in tables.zip(super::traits::get_bases(&input.valid())) {
+            result += base * NS1::read_value(input, values);
         }
-        NS2::try_from_input(result, input).unwrap()
+        result
     }
 }
 
@@ -162,4 +185,90 @@ mod tests {
         let input = vec![vec![3, 3], vec![2, 2]];
         assert_eq!(big(NS2::read_values(&input, &buf)), 103);
     }
+
+    #[test]
+    fn test_no_capacity() {
+        // Tables that can't hold a digit sit between ones that can, without shifting them
+        let input = vec![
+            vec![0, 0],
+            vec![2, 2],
+            vec![1, 0, 1],
+            vec![0; 16],
+            vec![3, 3],
+        ];
+        let values = vec![
+            vec![],
+            vec![0, 2, 4, 6],
+            vec![9, 8],
+            vec![],
+            vec![0, 5, 10, 15, 20, 25],
+        ];
+
+        for value in [0, 1, 103, 143] {
+            let ns = n(value, &input).unwrap();
+            let mut buf = values.clone();
+            ns.permute_values(&mut buf);
+            assert_eq!(buf[2], values[2]);
+            assert_eq!(big(NS2::read_values(&input, &buf)), value);
+        }
+        assert!(n(144, &input).is_none());
+    }
+
+    mod properties {
+        use proptest::prelude::*;
+
+        use super::*;
+        use crate::fns::MaxBaseValue;
+
+        // A DHT table's count of codes per length: the histogram of up to 256 code lengths, with
+        // the longest lengths over-represented, as in typical AC tables
+        fn table_sizes() -> impl Strategy<Value = Vec<u8>> {
+            let code_length = prop_oneof![3 => 1..=16usize, 1 => Just(16usize)];
+            prop::collection::vec(code_length, 1..=256).prop_map(|code_lengths| {
+                let mut sizes = vec![0u8; 16];
+                for length in code_lengths {
+                    sizes[length - 1] += 1;
+                }
+                sizes
+            })
+        }
+
+        // Each table's sizes, alongside its distinct values in some arbitrary order
+        fn tables() -> impl Strategy<Value = (Input, Input)> {
+            let table = table_sizes().prop_flat_map(|sizes| {
+                let count = sizes.iter().map(|&count| count as usize).sum::<usize>();
+                let values = Just((0..=255).collect::<Vec<u8>>())
+                    .prop_shuffle()
+                    .prop_map(move |values| values[..count].to_vec());
+                (Just(sizes), values)
+            });
+
+            prop::collection::vec(table, 1..=4).prop_map(|tables| tables.into_iter().unzip())
+        }
+
+        proptest! {
+            #[test]
+            fn test_round_trip(
+                (sizes, values) in tables(),
+                value in prop::collection::vec(any::<u8>(), 0..=512),
+            ) {
+                let max_value = sizes.max_base_value();
+                let value = BigUint::from_bytes_be(&value) % &max_value;
+
+                let ns = NS2::try_from_input(value.clone(), &sizes).unwrap();
+                let mut permuted = values.clone();
+                ns.permute_values(&mut permuted);
+
+                let read = BigUint::from(NS2::read_values(&sizes, &permuted));
+                prop_assert_eq!(read, value);
+            }
+
+            #[test]
+            fn test_max_value(sizes in tables().prop_map(|(sizes, _)| sizes)) {
+                let max_value = sizes.max_base_value();
+                prop_assert!(NS2::try_from_input(&max_value - 1u32, &sizes).is_some());
+                prop_assert!(NS2::try_from_input(max_value, &sizes).is_none());
+            }
+        }
+    }
 }
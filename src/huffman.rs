@@ -1,9 +1,12 @@
+use anyhow::{bail, Result};
+
 pub fn construct_huffman_table<U: AsRef<[u8]>, V: AsRef<[u8]>>(
     sizes: U,
     values: V,
-) -> Vec<(u8, Vec<u8>)> {
+) -> Result<Vec<(u8, Vec<u8>)>> {
     let sizes = sizes.as_ref();
     let values = values.as_ref();
+    check_huffman_table(sizes, values.len())?;
 
     let codes = sizes
         .iter()
@@ -14,7 +17,7 @@ pub fn construct_huffman_table<U: AsRef<[u8]>, V: AsRef<[u8]>>(
         })
         .collect::<Vec<_>>();
 
-    let mut code = 0u16;
+    let mut code = 0u32;
     let mut code_table = Vec::new();
     let mut values = values.iter();
     let mut last = None;
@@ -23,6 +26,11 @@ pub fn construct_huffman_table<U: AsRef<[u8]>, V: AsRef<[u8]>>(
         code <<= size - last_size;
         last_size = size;
         for _ in 0..count {
+            // [SPEC] C.2 -- A malformed table may ask for more codes than fit in the bit length
+            if size > 16 || code >> size != 0 {
+                bail!("Huffman table requires codes longer than 16 bits");
+            }
+
             let &value = values.next().unwrap();
             let bits = bin_to_vec(code, size);
             code_table.push((value, bits));
@@ -35,7 +43,38 @@ pub fn construct_huffman_table<U: AsRef<[u8]>, V: AsRef<[u8]>>(
         code_table.push(last);
     }
 
-    code_table
+    Ok(code_table)
+}
+
+/// Checks that a table's counts of codes of each length can all be given codes, and that there's a
+/// value for each. [SPEC] C.2 assigns codes in order of length, so they fit only if together they
+/// leave some of the 16-bit code space spare: the all-ones code of any length is never used.
+pub fn check_huffman_table(sizes: &[u8], num_values: usize) -> Result<()> {
+    if sizes.len() != 16 {
+        bail!(
+            "Huffman table has code counts for {} lengths, rather than 16",
+            sizes.len()
+        );
+    }
+
+    let num_codes = sizes.iter().map(|&count| count as usize).sum::<usize>();
+    if num_codes != num_values {
+        bail!("Huffman table has {num_codes} codes, but {num_values} values");
+    }
+
+    // Each code of length n takes up 2^(16 - n) of the 2^16 16-bit codes
+    let used = sizes
+        .iter()
+        .enumerate()
+        .map(|(index, &count)| (count as u32) << (15 - index))
+        .sum::<u32>();
+    if used >= 1 << 16 {
+        bail!(
+            "Huffman table is oversubscribed: its codes take {used} of the 65536 16-bit codes, and must leave the all-ones code free"
+        );
+    }
+
+    Ok(())
 }
 
 fn bin_to_vec<T>(mut value: T, size: usize) -> Vec<u8>
@@ -80,3 +119,68 @@ pub fn print_huffman_table(table: &Vec<(u8, Vec<u8>)>) {
 }
 
 /////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_construct_huffman_table() -> Result<()> {
+        let sizes = [0, 1, 5, 1, 1, 1, 1, 1, 1, 0, 0, 0, 0, 0, 0, 0];
+        let values = (0..12).collect::<Vec<u8>>();
+        let table = construct_huffman_table(sizes, &values)?;
+
+        assert_eq!(table[0], (0, vec![0, 0]));
+        assert_eq!(table[1], (1, vec![0, 1, 0]));
+        assert_eq!(table[11], (11, vec![1, 1, 1, 1, 1, 1, 1, 1, 0]));
+        Ok(())
+    }
+
+    #[test]
+    fn test_construct_huffman_table_overflow() {
+        // Three 1-bit codes
+        let mut sizes = [0; 16];
+        sizes[0] = 3;
+        assert!(construct_huffman_table(sizes, [0, 1, 2]).is_err());
+
+        // 255 8-bit codes leave room for only 256 16-bit codes
+        let mut sizes = [0; 16];
+        sizes[7] = 255;
+        sizes[15] = 255;
+        assert!(construct_huffman_table(sizes, [0; 510]).is_ok());
+        sizes[14] = 1;
+        assert!(construct_huffman_table(sizes, [0; 511]).is_err());
+
+        // Lengths beyond 16 bits
+        let mut sizes = [0; 17];
+        sizes[16] = 1;
+        assert!(construct_huffman_table(sizes, [0]).is_err());
+    }
+
+    #[test]
+    fn test_check_huffman_table() {
+        let sizes = [0, 1, 5, 1, 1, 1, 1, 1, 1, 0, 0, 0, 0, 0, 0, 0];
+        assert!(check_huffman_table(&sizes, 12).is_ok());
+        let error = check_huffman_table(&sizes, 11).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Huffman table has 12 codes, but 11 values"
+        );
+
+        // Two 1-bit codes would need the all-ones code, as would four 2-bit codes
+        let mut sizes = [0; 16];
+        sizes[0] = 2;
+        let error = check_huffman_table(&sizes, 2).unwrap_err();
+        assert!(error.to_string().contains("oversubscribed"), "{error}");
+        assert!(construct_huffman_table(sizes, [0, 1]).is_err());
+        sizes[0] = 1;
+        sizes[1] = 1;
+        assert!(check_huffman_table(&sizes, 2).is_ok());
+        sizes[1] = 2;
+        assert!(check_huffman_table(&sizes, 3).is_err());
+
+        // Oversubscribed at every length, far beyond what the counts' sum would show
+        let sizes = [255; 16];
+        assert!(check_huffman_table(&sizes, 255 * 16).is_err());
+    }
+}
@@ -2,11 +2,19 @@
  * Some sections of this code were pulled from the Rust jpeg-decoder library.
  */
 
-use anyhow::Result;
+use anyhow::{anyhow, bail, Result};
 
-use crate::rw_stream::{HuffmanRWTree, RWStream};
+use crate::{
+    rw_stream::{EntropyRead, HuffmanRWTree, RWStream, ReadStream},
+    StegError,
+};
 
-use super::{segments::Component, Jpeg};
+use super::{
+    mcu::{mcu_range, scan_mcu_range, scan_num_samples},
+    segments::{Component, DhtData, DriData, SofData, SosData},
+    Jpeg,
+    Marker::*,
+};
 
 struct ComponentInfo<'a> {
     component: &'a Component,
@@ -15,38 +23,282 @@ struct ComponentInfo<'a> {
 }
 
 pub fn process_entropy_stream(jpeg: &Jpeg, in_data: &Vec<u8>) -> Result<Vec<u8>> {
-    let components_info = get_components_info(jpeg);
-    let (mcu_horizontal_samples, mcu_vertical_samples) = get_num_samples(&components_info);
-    let (max_mcu_x, max_mcu_y) = get_mcu_range(jpeg, &components_info);
+    let mut out_data = Vec::new();
+    process_entropy_stream_into(jpeg, in_data, &mut out_data)?;
+    Ok(out_data)
+}
 
-    let mut eob_run = 0;
-    let mut mcus_left_until_restart = jpeg.restart_interval;
+/// As `process_entropy_stream`, but re-encodes into `out_data`, whose allocation can be reused
+/// across scans
+pub fn process_entropy_stream_into(
+    jpeg: &Jpeg,
+    in_data: &Vec<u8>,
+    out_data: &mut Vec<u8>,
+) -> Result<()> {
+    let components_info = get_components_info(jpeg)?;
+    check_restart_markers(jpeg, &components_info, in_data)?;
 
     let in_data = strip_stream_padding(in_data);
-    let mut out_data = Vec::with_capacity(in_data.len());
+    out_data.clear();
+    out_data.reserve(in_data.len());
     let mut marker_positions = Vec::new();
-    let mut read_writer = RWStream::new(&in_data, &mut out_data);
+    let mut read_writer = RWStream::new(&in_data, out_data);
+
+    // Restarts that move (see `Jpeg::set_restart_interval`) change which blocks' DC coefficients
+    // are predicted from zero, so those are decoded in full and written afresh
+    let output_interval = jpeg
+        .output_restart_interval
+        .unwrap_or(jpeg.restart_interval);
+    let rechunk = output_interval != jpeg.restart_interval;
+    let components = components_info
+        .iter()
+        .map(|info| info.component)
+        .collect::<Vec<_>>();
+    let (mcu_horizontal_samples, mcu_vertical_samples) = scan_num_samples(&components);
+    let blocks_per_mcu = mcu_horizontal_samples
+        .iter()
+        .zip(&mcu_vertical_samples)
+        .map(|(h, v)| h * v)
+        .sum::<u32>();
+    let mut blocks_read = 0;
+    let mut restarts_written = 0;
+    let mut predictions = vec![0; components_info.len()];
+    let mut output_predictions = vec![0; components_info.len()];
+
+    let decoded = traverse_mcus(
+        jpeg,
+        &components_info,
+        &mut read_writer,
+        |read_writer, step| {
+            match step {
+                Step::Restart if !rechunk => {
+                    // We should have a byte-aligned RST marker here, let's process it
+                    read_writer.byte_align()?;
+                    marker_positions.push(read_writer.writer_position());
+                    read_restart_marker(read_writer)
+                }
+                Step::Restart => {
+                    read_writer.set_mirror(false);
+                    read_writer.byte_align()?;
+                    let marker = read_restart_marker(read_writer);
+                    read_writer.set_mirror(true);
+                    predictions
+                        .iter_mut()
+                        .for_each(|prediction| *prediction = 0);
+                    marker
+                }
+                Step::Block { eob_run, .. } if !rechunk => {
+                    decode_block(read_writer, jpeg, eob_run, None)
+                }
+                Step::Block { component, eob_run } => {
+                    let mcu = blocks_read / blocks_per_mcu;
+                    let starts_mcu = blocks_read % blocks_per_mcu == 0;
+                    if starts_mcu && output_interval > 0 && mcu > 0 && mcu % output_interval == 0 {
+                        read_writer.pad_writer()?;
+                        marker_positions.push(read_writer.writer_position());
+                        read_writer.write::<u16>(16, 0xFFD0 + restarts_written % 8)?;
+                        restarts_written += 1;
+                        output_predictions
+                            .iter_mut()
+                            .for_each(|prediction| *prediction = 0);
+                    }
+                    blocks_read += 1;
+
+                    rechunk_block(
+                        read_writer,
+                        jpeg,
+                        eob_run,
+                        &mut predictions[component],
+                        &mut output_predictions[component],
+                    )
+                }
+            }
+        },
+    );
+    check_decoded_to_end(&mut read_writer, &in_data, decoded)?;
+    read_writer.byte_align()?;
+
+    insert_data_padding(out_data, &marker_positions);
+    Ok(())
+}
+
+/// The quantized DCT coefficients of one frame component, one block at a time
+pub struct ComponentCoefficients {
+    pub component_id: u32,
+    pub blocks_across: u32,
+    pub blocks_down: u32,
+    /// Row by row, each block's 64 coefficients in zig-zag order, the DC coefficient first
+    pub blocks: Vec<[i32; 64]>,
+}
+
+/// Every frame component's coefficients, in frame order
+pub struct CoefficientGrid {
+    pub components: Vec<ComponentCoefficients>,
+}
+
+impl CoefficientGrid {
+    fn new(frame: &SofData) -> Self {
+        let all_components = frame.components.iter().collect::<Vec<_>>();
+        let (max_mcu_x, max_mcu_y) = mcu_range(frame, &all_components);
+
+        let components = frame
+            .components
+            .iter()
+            .map(|component| {
+                let blocks_across = max_mcu_x * component.h_factor;
+                let blocks_down = max_mcu_y * component.v_factor;
+                ComponentCoefficients {
+                    component_id: component.component_id,
+                    blocks_across,
+                    blocks_down,
+                    blocks: vec![[0; 64]; (blocks_across * blocks_down) as usize],
+                }
+            })
+            .collect();
+
+        Self { components }
+    }
+}
 
-    for mcu_y in 0..max_mcu_y {
-        if mcu_y * 8 >= jpeg.frame.height {
-            break;
+impl Jpeg {
+    /// The quantized coefficients of every block in the image, decoded without re-encoding
+    /// anything. Only sequential files are supported.
+    pub fn decode_coefficients(&self) -> Result<CoefficientGrid> {
+        let mut state = Jpeg::default();
+        let mut grid = None;
+
+        for segment in &self.segments {
+            match segment.marker {
+                SOF0 | SOF1 => {
+                    state.frame = self.frame_data(segment)?;
+                    grid = Some(CoefficientGrid::new(&state.frame));
+                }
+                SOF2 => bail!("Progressive JPEG files not supported"),
+                DHT => {
+                    for table in DhtData::try_from(&segment.data[..])?.tables {
+                        let tree = HuffmanRWTree::from_table(&table.sizes, &table.values)?;
+                        state.set_huffman_tree(table.table_class, table.table_index, tree);
+                    }
+                }
+                DRI => state.restart_interval = DriData::try_from(&segment.data[..])?.count,
+                SOS => {
+                    state.scan = SosData::try_from(&segment.data[..])?;
+                    let grid = grid
+                        .as_mut()
+                        .ok_or_else(|| anyhow!("Scan found ahead of the frame header"))?;
+                    decode_scan_coefficients(&state, grid)?;
+                }
+                _ => {}
+            }
         }
 
-        for mcu_x in 0..max_mcu_x {
-            if mcu_x * 8 >= jpeg.frame.width {
-                break;
+        grid.ok_or_else(|| anyhow!("No frame header found"))
+    }
+}
+
+/// Decodes the current scan's coefficients into `grid`, leaving the scan data as it is
+fn decode_scan_coefficients(jpeg: &Jpeg, grid: &mut CoefficientGrid) -> Result<()> {
+    let components_info = get_components_info(jpeg)?;
+    let grid_indices = components_info
+        .iter()
+        .map(|info| {
+            grid.components
+                .iter()
+                .position(|c| c.component_id == info.component.component_id)
+                .unwrap()
+        })
+        .collect::<Vec<_>>();
+
+    check_restart_markers(jpeg, &components_info, &jpeg.scan.image_data)?;
+    let in_data = strip_stream_padding(&jpeg.scan.image_data);
+    let mut reader = ReadStream::new(&in_data);
+
+    let components = components_info
+        .iter()
+        .map(|info| info.component)
+        .collect::<Vec<_>>();
+    let (mcus_across, _) = scan_mcu_range(&jpeg.frame, &components);
+    let (mcu_horizontal_samples, mcu_vertical_samples) = scan_num_samples(&components);
+    let mut predictions = vec![0i32; components_info.len()];
+    let mut block_counts = vec![0u32; components_info.len()];
+
+    let decoded = traverse_mcus(jpeg, &components_info, &mut reader, |reader, step| {
+        let (component, eob_run) = match step {
+            Step::Restart => {
+                // [SPEC] F.2.1.3.1 -- Predictions restart from zero after each RST
+                reader.byte_align()?;
+                predictions
+                    .iter_mut()
+                    .for_each(|prediction| *prediction = 0);
+                return read_restart_marker(reader);
             }
+            Step::Block { component, eob_run } => (component, eob_run),
+        };
+
+        // [SPEC] A.2.3 -- Each component's blocks within an MCU run left to right, top to bottom
+        let (h, v) = (
+            mcu_horizontal_samples[component],
+            mcu_vertical_samples[component],
+        );
+        let index = block_counts[component];
+        block_counts[component] += 1;
+        let (mcu, within) = (index / (h * v), index % (h * v));
+        let x = (mcu % mcus_across) * h + within % h;
+        let y = (mcu / mcus_across) * v + within / h;
+
+        let coefficients = &mut grid.components[grid_indices[component]];
+        let block_index = (y * coefficients.blocks_across + x) as usize;
+        let block = coefficients
+            .blocks
+            .get_mut(block_index)
+            .ok_or_else(|| anyhow!("Block ({x}, {y}) lies outside the frame"))?;
+
+        decode_block(reader, jpeg, eob_run, Some(block))?;
+        predictions[component] += block[0];
+        block[0] = predictions[component];
+        Ok(())
+    });
+    check_decoded_to_end(&mut reader, &in_data, decoded)
+}
 
-            if jpeg.restart_interval > 0 {
-                if mcus_left_until_restart == 0 {
-                    // We should have a byte-aligned RST marker here, let's process it
-                    read_writer.byte_align()?;
-                    marker_positions.push(read_writer.writer_position());
-                    let marker_header = read_writer.read::<u8>(8)?;
-                    assert_eq!(marker_header, 0xFF);
+enum Step<'e> {
+    /// A restart marker is due, ahead of the next MCU
+    Restart,
+    Block {
+        /// Index into the scan's components
+        component: usize,
+        eob_run: &'e mut u16,
+    },
+}
+
+/// Walks a scan's MCUs in order, handing each of their blocks, and each restart, to `visit`
+fn traverse_mcus<'a, S, F>(
+    jpeg: &Jpeg,
+    components_info: &[ComponentInfo<'a>],
+    stream: &mut S,
+    mut visit: F,
+) -> Result<()>
+where
+    S: EntropyRead<'a>,
+    F: FnMut(&mut S, Step<'_>) -> Result<()>,
+{
+    let components = components_info
+        .iter()
+        .map(|component_info| component_info.component)
+        .collect::<Vec<_>>();
+    let (mcu_horizontal_samples, mcu_vertical_samples) = scan_num_samples(&components);
+    let (max_mcu_x, max_mcu_y) = scan_mcu_range(&jpeg.frame, &components);
 
-                    read_writer.read::<u8>(8)?;
+    let mut eob_run = 0;
+    let mut mcus_left_until_restart = jpeg.restart_interval;
 
+    for _ in 0..max_mcu_y {
+        jpeg.check_continue()?;
+
+        for _ in 0..max_mcu_x {
+            if jpeg.restart_interval > 0 {
+                if mcus_left_until_restart == 0 {
+                    visit(stream, Step::Restart)?;
                     eob_run = 0;
                     mcus_left_until_restart = jpeg.restart_interval;
                 }
@@ -55,38 +307,189 @@ pub fn process_entropy_stream(jpeg: &Jpeg, in_data: &Vec<u8>) -> Result<Vec<u8>>
             }
 
             for (i, component_info) in components_info.iter().enumerate() {
-                let dc_table = &component_info.dc_tree;
-                let ac_table = &component_info.ac_tree;
-                read_writer.set_tables(dc_table, ac_table);
+                stream.set_tables(component_info.dc_tree, component_info.ac_tree);
 
                 for _v_pos in 0..mcu_vertical_samples[i] {
                     for _h_pos in 0..mcu_horizontal_samples[i] {
-                        decode_block(&mut read_writer, jpeg, &mut eob_run)?;
+                        let step = Step::Block {
+                            component: i,
+                            eob_run: &mut eob_run,
+                        };
+                        visit(stream, step)?;
                     }
                 }
             }
         }
     }
 
-    let out_data = insert_data_padding(&mut out_data, &marker_positions);
-    Ok(out_data)
+    Ok(())
 }
 
-fn decode_block(read_writer: &mut RWStream<'_>, jpeg: &Jpeg, eob_run: &mut u16) -> Result<()> {
-    if jpeg.scan.spectral_start == 0 {
-        // Section F.2.2.1
-        // Figure F.12
-
-        let value = read_writer.read_huffman_dc()?;
-        match value {
-            0 => {}
-            1..=11 => {
-                read_writer.read::<u16>(value.into())?;
+fn read_restart_marker<'a, S: EntropyRead<'a>>(stream: &mut S) -> Result<()> {
+    let marker = stream.read::<u16>(16)?;
+    if !(0xFFD0..=0xFFD7).contains(&marker) {
+        bail!("Expected a restart marker, found {marker:#06X}");
+    }
+    Ok(())
+}
+
+// A scan's restart markers have to match the interval, or the MCUs are read out of step with them
+fn check_restart_markers(
+    jpeg: &Jpeg,
+    components_info: &[ComponentInfo],
+    data: &[u8],
+) -> Result<()> {
+    let mut found = 0;
+    let mut bytes = data.iter();
+    while let Some(&byte) = bytes.next() {
+        if byte == 0xFF {
+            if let Some(0xD0..=0xD7) = bytes.next() {
+                found += 1;
             }
-            _ => panic!(),
         }
     }
 
+    let components = components_info
+        .iter()
+        .map(|info| info.component)
+        .collect::<Vec<_>>();
+    let (max_mcu_x, max_mcu_y) = scan_mcu_range(&jpeg.frame, &components);
+    let num_mcus = max_mcu_x * max_mcu_y;
+
+    match jpeg.restart_interval {
+        0 if found > 0 => bail!(
+            "Scan data has {found} restart markers, but no restart interval was defined (DRI)"
+        ),
+        0 => Ok(()),
+        interval => {
+            let expected = num_mcus.saturating_sub(1) / interval;
+            if found < expected {
+                bail!("Scan data has {found} restart markers, but {expected} are needed for its {num_mcus} MCUs at an interval of {interval}");
+            }
+            Ok(())
+        }
+    }
+}
+
+/// After the last MCU, only the 1-bit padding of the final byte should be left unread. The scan
+/// data stops short of the marker that follows it (EOI, for the last scan), so anything more, or
+/// running out of data partway through a block, means the decoder and the scan disagree about where
+/// the codes are.
+fn check_decoded_to_end<'a, S: EntropyRead<'a>>(
+    reader: &mut S,
+    data: &[u8],
+    decoded: Result<()>,
+) -> Result<()> {
+    let read_bits = reader.reader_bit_position();
+    let total_bits = 8 * data.len() as u64;
+
+    if let Err(error) = decoded {
+        let ran_out = matches!(
+            error.downcast_ref::<std::io::Error>(),
+            Some(error) if error.kind() == std::io::ErrorKind::UnexpectedEof
+        );
+        return match ran_out {
+            true => Err(StegError::EntropyDesync {
+                read_bits,
+                total_bits,
+            }
+            .into()),
+            false => Err(error),
+        };
+    }
+
+    if read_bits > total_bits || total_bits - read_bits >= 8 {
+        return Err(StegError::EntropyDesync {
+            read_bits,
+            total_bits,
+        }
+        .into());
+    }
+    Ok(())
+}
+
+/// Reads one block, which `coefficients` (if given) receives: the DC coefficient as the difference
+/// from the previous block's, and the AC coefficients in zig-zag order
+fn decode_block<'a, S: EntropyRead<'a>>(
+    read_writer: &mut S,
+    jpeg: &Jpeg,
+    eob_run: &mut u16,
+    mut coefficients: Option<&mut [i32; 64]>,
+) -> Result<()> {
+    if jpeg.scan.spectral_start == 0 {
+        let difference = decode_dc(read_writer, jpeg)?;
+        if let Some(coefficients) = coefficients.as_deref_mut() {
+            coefficients[0] = difference;
+        }
+    }
+
+    decode_ac(read_writer, jpeg, eob_run, coefficients)
+}
+
+/// As `decode_block`, but with the DC coefficient re-encoded as the difference from
+/// `output_prediction` rather than copied, for output whose restarts fall elsewhere than the
+/// input's. Both predictions are of the block's component, and are updated to this block's value.
+fn rechunk_block(
+    read_writer: &mut RWStream,
+    jpeg: &Jpeg,
+    eob_run: &mut u16,
+    prediction: &mut i32,
+    output_prediction: &mut i32,
+) -> Result<()> {
+    read_writer.set_mirror(false);
+    let difference = decode_dc(read_writer, jpeg);
+    read_writer.set_mirror(true);
+    *prediction += difference?;
+
+    let difference = *prediction - *output_prediction;
+    *output_prediction = *prediction;
+
+    // [SPEC] F.1.2.1.1 -- The category is the difference's bit length, and a negative difference
+    // is written as its one's complement
+    let category = 32 - difference.unsigned_abs().leading_zeros();
+    if category > jpeg.frame.precision + 3 {
+        bail!(
+            "DC difference {difference} is too large for {}-bit samples",
+            jpeg.frame.precision
+        );
+    }
+    read_writer.write_huffman_dc(category as u8)?;
+    if category > 0 {
+        let bits = match difference < 0 {
+            true => difference + (1 << category) - 1,
+            false => difference,
+        };
+        read_writer.write::<u16>(category, bits as u16)?;
+    }
+
+    decode_ac(read_writer, jpeg, eob_run, None)
+}
+
+// Section F.2.2.1
+// Figure F.12
+fn decode_dc<'a, S: EntropyRead<'a>>(read_writer: &mut S, jpeg: &Jpeg) -> Result<i32> {
+    // [SPEC] F.1.2.1.1 -- Differences take up to 11 bits for 8-bit samples, or 15 for 12-bit
+    let max_category = jpeg.frame.precision + 3;
+    let value = read_writer.read_huffman_dc()?;
+    Ok(match value as u32 {
+        0 => 0,
+        category if category <= max_category => {
+            let bits = read_writer.read::<u16>(value.into())?;
+            extend(bits, value)
+        }
+        _ => bail!(
+            "Invalid DC difference category {value} for {}-bit samples",
+            jpeg.frame.precision
+        ),
+    })
+}
+
+fn decode_ac<'a, S: EntropyRead<'a>>(
+    read_writer: &mut S,
+    jpeg: &Jpeg,
+    eob_run: &mut u16,
+    mut coefficients: Option<&mut [i32; 64]>,
+) -> Result<()> {
     let mut index = jpeg.scan.spectral_start.max(1);
     if index < jpeg.scan.spectral_end && *eob_run > 0 {
         *eob_run -= 1;
@@ -119,7 +522,10 @@ fn decode_block(read_writer: &mut RWStream<'_>, jpeg: &Jpeg, eob_run: &mut u16)
                 break;
             }
 
-            read_writer.read::<u16>(s.into())?;
+            let bits = read_writer.read::<u16>(s.into())?;
+            if let Some(coefficients) = coefficients.as_deref_mut() {
+                coefficients[index as usize] = extend(bits, s);
+            }
             index += 1;
         }
     }
@@ -127,6 +533,16 @@ fn decode_block(read_writer: &mut RWStream<'_>, jpeg: &Jpeg, eob_run: &mut u16)
     Ok(())
 }
 
+// [SPEC] F.2.2.1 -- A category's smaller half of its bit patterns stand for negative values
+fn extend(bits: u16, category: u8) -> i32 {
+    let (bits, category) = (bits as i32, category as u32);
+    match category {
+        0 => 0,
+        _ if bits < 1 << (category - 1) => bits - (1 << category) + 1,
+        _ => bits,
+    }
+}
+
 fn strip_stream_padding(in_data: &Vec<u8>) -> Vec<u8> {
     let mut fixed_data = Vec::with_capacity(in_data.len());
     let mut data_iter = in_data.iter().cloned();
@@ -142,18 +558,31 @@ fn strip_stream_padding(in_data: &Vec<u8>) -> Vec<u8> {
     fixed_data
 }
 
-fn insert_data_padding(data: &mut Vec<u8>, marker_positions: &[usize]) -> Vec<u8> {
-    let mut out_data = Vec::new();
-    for (index, value) in data.drain(..).enumerate() {
-        out_data.push(value);
-        if value == 0xFF && !marker_positions.contains(&index) {
-            out_data.push(0x00);
+// Stuffs a 0x00 after every 0xFF that isn't a marker, in place: the data is grown once, then
+// shifted into its final position from the end backwards
+fn insert_data_padding(data: &mut Vec<u8>, marker_positions: &[usize]) {
+    let needs_padding =
+        |index: usize, value: u8| value == 0xFF && marker_positions.binary_search(&index).is_err();
+
+    let old_len = data.len();
+    let padding = (0..old_len)
+        .filter(|&index| needs_padding(index, data[index]))
+        .count();
+    data.resize(old_len + padding, 0);
+
+    let mut write_index = data.len();
+    for read_index in (0..old_len).rev() {
+        let value = data[read_index];
+        if needs_padding(read_index, value) {
+            write_index -= 1;
+            data[write_index] = 0x00;
         }
+        write_index -= 1;
+        data[write_index] = value;
     }
-    out_data
 }
 
-fn get_components_info(jpeg: &Jpeg) -> Vec<ComponentInfo> {
+fn get_components_info(jpeg: &Jpeg) -> Result<Vec<ComponentInfo<'_>>> {
     let mut components = Vec::new();
     for scan_component in &jpeg.scan.components {
         let component_index = jpeg
@@ -161,7 +590,12 @@ fn get_components_info(jpeg: &Jpeg) -> Vec<ComponentInfo> {
             .components
             .iter()
             .position(|c| c.component_id == scan_component.component_id)
-            .unwrap();
+            .ok_or_else(|| {
+                anyhow!(
+                    "Scan references component {}, which isn't in the frame",
+                    scan_component.component_id
+                )
+            })?;
 
         let component = &jpeg.frame.components[component_index];
         let (dc_table, ac_table) =
@@ -173,35 +607,66 @@ fn get_components_info(jpeg: &Jpeg) -> Vec<ComponentInfo> {
             ac_tree: ac_table,
         });
     }
-    components
+    Ok(components)
 }
 
-fn get_num_samples(components_info: &[ComponentInfo]) -> (Vec<u32>, Vec<u32>) {
-    let horizontal = components_info
-        .iter()
-        .map(|component_info| component_info.component.h_factor)
-        .collect::<Vec<_>>();
-    let vertical = components_info
-        .iter()
-        .map(|component_info| component_info.component.v_factor)
-        .collect::<Vec<_>>();
-    (horizontal, vertical)
-}
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-fn get_mcu_range(jpeg: &Jpeg, components_info: &[ComponentInfo]) -> (u32, u32) {
-    let h_max = components_info
-        .iter()
-        .map(|c| c.component.h_factor)
-        .max()
-        .unwrap();
-    let v_max = components_info
-        .iter()
-        .map(|c| c.component.v_factor)
-        .max()
-        .unwrap();
-
-    (
-        (jpeg.frame.width + h_max * 8 - 1) / (h_max * 8),
-        (jpeg.frame.height + v_max * 8 - 1) / (v_max * 8),
-    )
+    #[test]
+    fn test_insert_data_padding() {
+        let mut data = vec![0x12, 0xFF, 0x34, 0xFF, 0xD0, 0xFF];
+        insert_data_padding(&mut data, &[3]);
+        assert_eq!(data, [0x12, 0xFF, 0x00, 0x34, 0xFF, 0xD0, 0xFF, 0x00]);
+
+        let mut data = vec![0xFF, 0xFF];
+        insert_data_padding(&mut data, &[]);
+        assert_eq!(data, [0xFF, 0x00, 0xFF, 0x00]);
+
+        let mut data = vec![];
+        insert_data_padding(&mut data, &[]);
+        assert!(data.is_empty());
+    }
+
+    #[test]
+    fn test_decode_coefficients() -> Result<()> {
+        use std::io::Cursor;
+
+        use crate::jpeg::test_image::TestImage;
+
+        // With the Annex K luminance tables: DC category 2 (011) with bits 11, AC 0x01 (00) with
+        // bit 0, AC 0x02 (01) with bits 10, then EOB (1010)
+        let block = [0b0111_1000, 0b0110_1010];
+        let with_scan = |image: TestImage, scan: &[u8]| {
+            let mut data = image.components(&[(1, 1, 1)]).build();
+            let sos = data
+                .windows(2)
+                .position(|bytes| bytes == [0xFF, 0xDA])
+                .unwrap();
+            let end = data.len() - 2;
+            data.splice(sos + 10..end, scan.iter().copied());
+            Jpeg::read_segments(&mut Cursor::new(data))
+        };
+
+        let mut expected = [0; 64];
+        expected[..3].copy_from_slice(&[3, -1, 2]);
+
+        let grid = with_scan(TestImage::new(8, 8), &block)?.decode_coefficients()?;
+        assert_eq!(grid.components.len(), 1);
+        assert_eq!(grid.components[0].blocks, [expected]);
+
+        // The second block's DC is predicted from the first's, unless a restart comes between
+        let scan = [block, block].concat();
+        let grid = with_scan(TestImage::new(16, 8), &scan)?.decode_coefficients()?;
+        assert_eq!(grid.components[0].blocks_across, 2);
+        assert_eq!(grid.components[0].blocks[1][..3], [6, -1, 2]);
+
+        let scan = [&block[..], &[0xFF, 0xD0], &block].concat();
+        let image = TestImage::new(16, 8).restart_interval(1);
+        let grid = with_scan(image, &scan)?.decode_coefficients()?;
+        assert_eq!(grid.components[0].blocks, [expected, expected]);
+
+        Ok(())
+    }
 }
@@ -1,10 +1,12 @@
 use std::io::{Read, Write};
 
-use anyhow::Result;
+use anyhow::{anyhow, bail, Result};
 
-use crate::rw_stream::HuffmanRWTree;
+use crate::{rw_stream::HuffmanRWTree, StegError};
 
 use super::{
+    default_tables::default_dht_data,
+    marker::CodingMode,
     segments::*,
     Marker::{self, *},
 };
@@ -16,30 +18,140 @@ pub struct Segment {
     pub data: Vec<u8>,
 }
 
+/// A segment's data, parsed into the form its marker calls for
+pub enum ParsedSegment<'a> {
+    Frame(SofData),
+    Scan(SosData),
+    Quantization(DqtData),
+    Huffman(DhtData),
+    RestartInterval(DriData),
+    NumberOfLines(DnlData),
+    /// The data as it is, for markers without a typed form
+    Raw(&'a [u8]),
+}
+
+impl Segment {
+    /// Parses the segment's data with the `TryFrom` its marker calls for. A scan's image data,
+    /// which follows its header, is left out.
+    pub fn parse(&self) -> Result<ParsedSegment<'_>> {
+        let data = &self.data[..];
+        Ok(match self.marker {
+            SOF0 | SOF1 | SOF2 => ParsedSegment::Frame(SofData::try_from(data)?),
+            SOS => ParsedSegment::Scan(SosData::try_from(data)?),
+            DQT => ParsedSegment::Quantization(DqtData::try_from(data)?),
+            DHT => ParsedSegment::Huffman(DhtData::try_from(data)?),
+            DRI => ParsedSegment::RestartInterval(DriData::try_from(data)?),
+            DNL => ParsedSegment::NumberOfLines(DnlData::try_from(data)?),
+            _ => ParsedSegment::Raw(data),
+        })
+    }
+}
+
 #[derive(Default)]
 pub struct Jpeg {
     pub frame: SofData,
     pub huffman_trees: [HuffmanRWTree; 4],
     pub restart_interval: u32,
+    /// Set by `set_restart_interval`, in place of the file's own interval when scans are re-encoded
+    pub output_restart_interval: Option<u32>,
     pub scan: SosData,
     pub segments: Vec<Segment>,
+    /// Any bytes following EOI, which some tools use to append their own data
+    pub trailer: Vec<u8>,
+    /// Checked before each segment and through each scan's image data when processing with
+    /// `process_segments_mut`; returning false stops it with `StegError::Cancelled`
+    pub should_continue: Option<Box<dyn Fn() -> bool + Send + Sync>>,
 }
 
 impl Jpeg {
     pub fn read_segments<R: Read>(reader: &mut R) -> Result<Self> {
         let mut buf = Vec::new();
         reader.read_to_end(&mut buf)?;
+        Self::from_bytes(&buf)
+    }
+
+    /// As `read_segments`, for a file that's already in memory (e.g. memory-mapped). A file that
+    /// ends without an EOI marker is given one, after all of its data.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        if !bytes.starts_with(&[0xFF, 0xD8]) {
+            return Err(StegError::NotAJpeg.into());
+        }
 
-        let sections = Self::scan_segments(buf);
+        let (sections, trailer) = Self::scan_segments(bytes)?;
         Ok(Self {
             segments: sections,
+            trailer,
             ..Default::default()
         })
     }
 
-    fn scan_segments(bytes: Vec<u8>) -> Vec<Segment> {
+    /// Reads just the segments ahead of the first scan, leaving the (typically much larger) image
+    /// data unread. Files whose tables may change between scans (progressive, or sequential with
+    /// one scan per component) still have to be read in full.
+    pub fn read_headers_only<R: Read>(reader: &mut R) -> Result<Self> {
+        let mut bytes = Vec::new();
+        match Self::read_headers(reader, |read| bytes.extend(read))? {
+            Some(jpeg) => Ok(jpeg),
+            None => {
+                reader.read_to_end(&mut bytes)?;
+                Self::from_bytes(&bytes)
+            }
+        }
+    }
+
+    /// As `read_headers_only`, for a file that's already in memory. This only saves copying the
+    /// whole file into a buffer first: the segments don't borrow from `bytes`, so each header
+    /// segment is still copied into its own `Vec`, and a file whose scans have to be read after
+    /// all is copied in full.
+    pub fn headers_from_bytes(bytes: &[u8]) -> Result<Self> {
+        match Self::read_headers(&mut &bytes[..], |_| {})? {
+            Some(jpeg) => Ok(jpeg),
+            None => Self::from_bytes(bytes),
+        }
+    }
+
+    /// Reads and yields one segment at a time, up to and including the first scan header, leaving
+    /// the image data that follows it unread. An error ends the iteration.
+    pub fn segments_iter<R: Read>(reader: R) -> impl Iterator<Item = Result<Segment>> {
+        SegmentIter::new(reader, |_: &[u8]| {})
+    }
+
+    /// The segments up to and including the first scan header, with each byte read passed to
+    /// `record`. None if the file has to be read in full after all.
+    fn read_headers<R: Read>(reader: &mut R, record: impl FnMut(&[u8])) -> Result<Option<Self>> {
+        let mut segments = Vec::new();
+        let mut frame = SofData::default();
+        let mut progressive = false;
+
+        for segment in SegmentIter::new(reader, record) {
+            let segment = segment?;
+            match segment.marker {
+                SOF0 | SOF1 | SOF2 => {
+                    frame = SofData::try_from(&segment.data[..])?;
+                    progressive = segment.marker == SOF2;
+                }
+                SOS => {
+                    let scan = SosData::try_from(&segment.data[..])?;
+                    if progressive || scan.components.len() < frame.components.len() {
+                        return Ok(None);
+                    }
+                }
+                _ => {}
+            }
+
+            segments.push(segment);
+        }
+
+        Ok(Some(Self {
+            segments,
+            ..Default::default()
+        }))
+    }
+
+    fn scan_segments(bytes: &[u8]) -> Result<(Vec<Segment>, Vec<u8>)> {
         use Marker::*;
         let mut markers = Vec::new();
+        let mut trailer_start = None;
 
         let mut index = 0;
         while index < bytes.len() - 1 {
@@ -65,40 +177,117 @@ impl Jpeg {
                     markers.push((index, marker));
                 }
             }
+            let marker_index = index;
             index += 2;
-        }
 
-        let mut sections = Vec::new();
-        let mut section: Option<Segment> = None;
-        let mut prev_index = 2;
-        for (index, marker) in markers {
-            if let Some(ref section) = section {
-                let offset = match section.marker {
-                    SOI | EOI | RST(_) => 0,
-                    _ => 2,
+            // Whatever follows the image isn't ours to interpret
+            if marker == EOI {
+                trailer_start = Some(index);
+                break;
+            }
+
+            // Skip over the declared length, so stray 0xFF bytes in a payload (e.g. a comment)
+            // aren't taken for markers. The entropy-coded data after a scan header is still
+            // searched, as it has no length of its own.
+            if marker.has_length() {
+                let length = match bytes.get(index..index + 2) {
+                    Some(length) => u16::from_be_bytes([length[0], length[1]]) as usize,
+                    None => bail!("{marker:?} at offset {marker_index} is missing its length"),
                 };
-                sections.push(Segment {
-                    data: bytes[prev_index + offset..index].to_vec(),
-                    ..*section
-                });
-                prev_index = index + 2;
+
+                let end = index + length;
+                if length < 2 || end > bytes.len() {
+                    bail!("{marker:?} at offset {marker_index} has invalid length {length}");
+                }
+                if marker != SOS && matches!(bytes.get(end), Some(&byte) if byte != 0xFF) {
+                    bail!("{marker:?} at offset {marker_index} isn't followed by a marker after its length of {length}");
+                }
+
+                index = end;
             }
+        }
 
-            section = Some(Segment {
-                index,
-                marker,
-                data: Vec::new(),
+        // Each segment's data runs from after its marker (and length) to the next marker, or for
+        // the last, to the end of the file if there's no EOI. A file cut just after a 0xFF loses
+        // it, as what followed it to make it data or a marker is gone.
+        let end = match trailer_start {
+            Some(start) => start,
+            None if index == bytes.len() - 1 && bytes[index] == 0xFF => index,
+            None => bytes.len(),
+        };
+        let ends = markers.iter().skip(1).map(|&(index, _)| index).chain([end]);
+        let mut sections = markers
+            .iter()
+            .zip(ends)
+            .map(|(&(index, marker), end)| {
+                let start = index + if marker.has_length() { 4 } else { 2 };
+                Segment {
+                    index,
+                    marker,
+                    data: bytes[start.min(end)..end].to_vec(),
+                }
             })
-        }
+            .collect::<Vec<_>>();
 
-        if let Some(ref section) = section {
+        // A file cut short of its EOI is given one, so that what's written from it is whole
+        if trailer_start.is_none() {
             sections.push(Segment {
-                data: bytes[prev_index..index].to_vec(),
-                ..*section
+                index: end,
+                marker: EOI,
+                data: Vec::new(),
             });
         }
 
-        sections
+        let trailer = trailer_start
+            .map(|start| bytes[start..].to_vec())
+            .unwrap_or_default();
+
+        Ok((sections, trailer))
+    }
+
+    /// The coding mode given by the file's frame header
+    pub fn coding_mode(&self) -> Result<CodingMode> {
+        match self
+            .segments
+            .iter()
+            .find_map(|segment| CodingMode::from_marker(segment.marker))
+        {
+            None => bail!("No frame header found"),
+            Some(mode) => Ok(mode),
+        }
+    }
+
+    /// Has re-encoded scans written with a restart marker every `count` MCUs, or none for 0, and
+    /// the DRI segment to match. The interval must fit the DRI segment's 16 bits.
+    pub fn set_restart_interval(&mut self, count: u32) {
+        self.output_restart_interval = Some(count);
+    }
+
+    pub fn has_huffman_tables(&self) -> bool {
+        self.segments.iter().any(|segment| segment.marker == DHT)
+    }
+
+    /// Makes the decoder-default tables explicit, placing them just ahead of the first scan.
+    pub fn inject_default_huffman_tables(&mut self) {
+        let position = self
+            .segments
+            .iter()
+            .position(|segment| segment.marker == SOS)
+            .unwrap_or(self.segments.len());
+        let index = self
+            .segments
+            .get(position)
+            .map(|segment| segment.index)
+            .unwrap_or_default();
+
+        self.segments.insert(
+            position,
+            Segment {
+                index,
+                marker: DHT,
+                data: default_dht_data().to_vec(),
+            },
+        );
     }
 
     pub fn process_segments_mut<P>(&mut self, mut processor: P) -> Result<()>
@@ -107,8 +296,9 @@ impl Jpeg {
     {
         let segments = self.segments.clone();
         for segment in segments {
+            self.check_continue()?;
             match segment.marker {
-                SOF0 | SOF1 | SOF2 => self.frame = SofData::try_from(&segment.data[..])?,
+                SOF0 | SOF1 | SOF2 => self.frame = self.frame_data(&segment)?,
                 SOS => self.scan = SosData::try_from(&segment.data[..])?,
                 DRI => {
                     let dri_data = DriData::try_from(&segment.data[..])?;
@@ -123,6 +313,30 @@ impl Jpeg {
         Ok(())
     }
 
+    /// The frame header in `segment`, with a height of 0 replaced by the one the DNL segment
+    /// after the first scan gives, so the scans' MCUs can be counted up front
+    pub(crate) fn frame_data(&self, segment: &Segment) -> Result<SofData> {
+        let mut frame = SofData::try_from(&segment.data[..])?;
+        if frame.height == 0 {
+            let dnl = self
+                .segments
+                .iter()
+                .find(|segment| segment.marker == DNL)
+                .ok_or_else(|| {
+                    anyhow!("Frame height is 0, but there's no DNL segment to give it")
+                })?;
+            frame.height = DnlData::try_from(&dnl.data[..])?.lines;
+        }
+        Ok(frame)
+    }
+
+    pub(crate) fn check_continue(&self) -> Result<()> {
+        match &self.should_continue {
+            Some(should_continue) if !should_continue() => Err(StegError::Cancelled.into()),
+            _ => Ok(()),
+        }
+    }
+
     pub fn process_segments<P>(&self, processor: P) -> Result<()>
     where
         P: ProcessSegment,
@@ -134,6 +348,15 @@ impl Jpeg {
         Ok(())
     }
 
+    /// Writes the segments and trailer out as they are, without re-encoding any image data
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> Result<()> {
+        for segment in &self.segments {
+            Self::write_segment(writer, segment)?;
+        }
+        writer.write_all(&self.trailer)?;
+        Ok(())
+    }
+
     pub fn write_segment<W: Write>(writer: &mut W, section: &Segment) -> Result<()> {
         let Segment { marker, data, .. } = section;
 
@@ -142,7 +365,7 @@ impl Jpeg {
 
         match *marker {
             SOI | EOI => {}
-            RST(_) => {
+            RST(_) | TEM => {
                 writer.write_all(data)?;
             }
             SOS => {
@@ -182,6 +405,99 @@ impl Jpeg {
     }
 }
 
+/// Reads segments one at a time up to the first scan header, passing each byte read to `record`
+struct SegmentIter<R, F> {
+    reader: R,
+    record: F,
+    position: usize,
+    done: bool,
+}
+
+impl<R: Read, F: FnMut(&[u8])> SegmentIter<R, F> {
+    fn new(reader: R, record: F) -> Self {
+        Self {
+            reader,
+            record,
+            position: 0,
+            done: false,
+        }
+    }
+
+    fn read(&mut self, buf: &mut [u8]) -> Result<()> {
+        self.reader.read_exact(buf)?;
+        (self.record)(buf);
+        self.position += buf.len();
+        Ok(())
+    }
+
+    fn read_segment(&mut self) -> Result<Segment> {
+        if self.position == 0 {
+            let mut soi = [0u8; 2];
+            if self.read(&mut soi).is_err() || soi != [0xFF, 0xD8] {
+                return Err(StegError::NotAJpeg.into());
+            }
+            return Ok(Segment {
+                index: 0,
+                marker: SOI,
+                data: Vec::new(),
+            });
+        }
+
+        let index = self.position;
+        let mut marker_bytes = [0u8; 2];
+        self.read(&mut marker_bytes)?;
+        if marker_bytes[0] != 0xFF {
+            bail!("Expected a marker at offset {index}");
+        }
+
+        // [SPEC] B.1.1.2 -- Any marker may be preceded by 0xFF fill bytes
+        while marker_bytes[1] == 0xFF {
+            self.read(&mut marker_bytes[1..])?;
+        }
+
+        let marker = Marker::from(marker_bytes[1]);
+        let data = match marker {
+            _ if !marker.has_length() => Vec::new(),
+            _ => {
+                let mut length = [0u8; 2];
+                self.read(&mut length)?;
+
+                let length = u16::from_be_bytes(length) as usize;
+                if length < 2 {
+                    bail!("Invalid length {length} for {marker:?} at offset {index}");
+                }
+
+                let mut data = vec![0; length - 2];
+                self.read(&mut data)?;
+                data
+            }
+        };
+
+        Ok(Segment {
+            index,
+            marker,
+            data,
+        })
+    }
+}
+
+impl<R: Read, F: FnMut(&[u8])> Iterator for SegmentIter<R, F> {
+    type Item = Result<Segment>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+
+        let segment = self.read_segment();
+        self.done = match &segment {
+            Ok(segment) => matches!(segment.marker, SOS | EOI),
+            Err(_) => true,
+        };
+        Some(segment)
+    }
+}
+
 pub trait ProcessSegmentMut {
     fn process_segment(&mut self, jpeg: &mut Jpeg, segment: &Segment) -> Result<()>;
 }
@@ -189,3 +505,286 @@ pub trait ProcessSegmentMut {
 pub trait ProcessSegment {
     fn process_segment(&self, jpeg: &Jpeg, segment: &Segment) -> Result<()>;
 }
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::jpeg::test_image::{decode_pixels, insert_segment, TestImage};
+
+    fn markers(data: &[u8]) -> Result<Vec<Marker>> {
+        let jpeg = Jpeg::read_segments(&mut Cursor::new(data))?;
+        Ok(jpeg.segments.iter().map(|segment| segment.marker).collect())
+    }
+
+    #[test]
+    fn test_segments_iter() -> Result<()> {
+        let input = TestImage::new(48, 32).build();
+        let headers = Jpeg::read_headers_only(&mut Cursor::new(&input))?;
+
+        let mut reader = Cursor::new(&input);
+        let segments = Jpeg::segments_iter(&mut reader).collect::<Result<Vec<_>>>()?;
+        assert_eq!(
+            segments
+                .iter()
+                .map(|s| (s.index, s.marker))
+                .collect::<Vec<_>>(),
+            headers
+                .segments
+                .iter()
+                .map(|s| (s.index, s.marker))
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(segments.last().unwrap().marker, SOS);
+
+        // The image data is left unread
+        let sos = segments.last().unwrap();
+        assert_eq!(reader.position() as usize, sos.index + 4 + sos.data.len());
+
+        // Stopping early reads no further than the segment asked for
+        let mut reader = Cursor::new(&input);
+        let dht = Jpeg::segments_iter(&mut reader).find(|s| matches!(s, Ok(s) if s.marker == DHT));
+        let dht = dht.unwrap()?;
+        assert_eq!(reader.position() as usize, dht.index + 4 + dht.data.len());
+
+        let mut segments = Jpeg::segments_iter(&b"not a jpeg"[..]);
+        let error = segments.next().unwrap().err().unwrap();
+        assert_eq!(error.downcast_ref(), Some(&StegError::NotAJpeg));
+        assert!(segments.next().is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse() -> Result<()> {
+        let mut input = TestImage::new(48, 32).build();
+        insert_segment(&mut input, 0xDA, 0, 0xDD, &[0, 3]);
+        let jpeg = Jpeg::read_segments(&mut Cursor::new(&input))?;
+
+        let mut parsed = Vec::new();
+        for segment in &jpeg.segments {
+            let kind = match segment.parse()? {
+                ParsedSegment::Frame(frame) => {
+                    assert_eq!((frame.width, frame.height), (48, 32));
+                    "frame"
+                }
+                ParsedSegment::Scan(scan) => {
+                    assert_eq!(scan.components.len(), 3);
+                    "scan"
+                }
+                ParsedSegment::Quantization(_) => "quantization",
+                ParsedSegment::Huffman(dht) => {
+                    assert!(!dht.tables.is_empty());
+                    "huffman"
+                }
+                ParsedSegment::RestartInterval(dri) => {
+                    assert_eq!(dri.count, 3);
+                    "restart interval"
+                }
+                ParsedSegment::NumberOfLines(_) => "number of lines",
+                ParsedSegment::Raw(data) => {
+                    assert_eq!(data, &segment.data[..]);
+                    "raw"
+                }
+            };
+            parsed.push(kind);
+        }
+        for kind in [
+            "frame",
+            "scan",
+            "quantization",
+            "huffman",
+            "restart interval",
+            "raw",
+        ] {
+            assert!(parsed.contains(&kind), "no {kind} segment parsed");
+        }
+
+        // A typed segment whose data doesn't parse is an error
+        let segment = Segment {
+            index: 0,
+            marker: DNL,
+            data: vec![0],
+        };
+        assert!(segment.parse().is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_missing_eoi() -> Result<()> {
+        use crate::processors::DhtWriter;
+
+        let data = TestImage::new(48, 32).build();
+        let truncated = &data[..data.len() - 2];
+        assert_eq!(markers(truncated)?, markers(&data)?);
+
+        // The scan keeps every byte up to the end of the file
+        let jpeg = Jpeg::read_segments(&mut Cursor::new(truncated))?;
+        let scan = jpeg.segments.iter().find(|s| s.marker == SOS).unwrap();
+        assert_eq!(scan.index + 4 + scan.data.len(), truncated.len());
+        let mut output = Vec::new();
+        jpeg.write_to(&mut output)?;
+        assert_eq!(output, data);
+
+        let mut output = Vec::new();
+        let mut jpeg = Jpeg::read_segments(&mut Cursor::new(truncated))?;
+        let writer = DhtWriter::new(&mut output, |_: &mut _| {}).always_reencode();
+        jpeg.process_segments_mut(writer)?;
+        assert!(output.ends_with(&[0xFF, 0xD9]));
+        assert_eq!(decode_pixels(&output), decode_pixels(&data));
+
+        // A 0xFF cut off from the 0x00 after it is dropped, so it isn't read with the EOI
+        let data = include_bytes!("../../docs/dove-small-in.jpg");
+        let scan = data
+            .windows(2)
+            .position(|bytes| bytes == [0xFF, 0xDA])
+            .unwrap();
+        let cut = scan
+            + data[scan..]
+                .windows(2)
+                .position(|bytes| bytes == [0xFF, 0x00])
+                .unwrap();
+        let jpeg = Jpeg::read_segments(&mut Cursor::new(&data[..cut + 1]))?;
+        let scan = jpeg.segments.iter().find(|s| s.marker == SOS).unwrap();
+        assert_eq!(scan.index + 4 + scan.data.len(), cut);
+        let mut output = Vec::new();
+        jpeg.write_to(&mut output)?;
+        assert_eq!(output, [&data[..cut], &[0xFF, 0xD9]].concat());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_segment_lengths() -> Result<()> {
+        let mut data = TestImage::new(16, 16).build();
+        let comment = [b'h', b'i', 0xFF, 0xD8, 0xFF, 0xC4, b'!'];
+        insert_segment(&mut data, 0xDB, 0, 0xFE, &comment);
+
+        assert_eq!(
+            markers(&data)?,
+            [SOI, APP(0), COM, DQT, SOF0, DHT, SOS, EOI]
+        );
+
+        let jpeg = Jpeg::read_segments(&mut Cursor::new(&data))?;
+        assert_eq!(jpeg.segments[2].data, comment);
+
+        // Lengths that run short, past the next marker, or past the end of the file
+        let com = data
+            .windows(2)
+            .position(|bytes| bytes == [0xFF, 0xFE])
+            .unwrap();
+        for length in [8, 10, 0xFFFF, 1] {
+            let mut data = data.clone();
+            data[com + 2..com + 4].copy_from_slice(&(length as u16).to_be_bytes());
+            assert!(markers(&data).is_err());
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_nested_thumbnail() -> Result<()> {
+        // An Exif APP1 carrying a whole JPEG as its thumbnail, DHT, scan and EOI included
+        let mut exif = b"Exif\0\0".to_vec();
+        exif.extend(TestImage::new(8, 8).build());
+        let mut data = TestImage::new(16, 16).build();
+        insert_segment(&mut data, 0xDB, 0, 0xE1, &exif);
+
+        assert_eq!(
+            markers(&data)?,
+            [SOI, APP(0), APP(1), DQT, SOF0, DHT, SOS, EOI]
+        );
+
+        let jpeg = Jpeg::read_segments(&mut Cursor::new(&data))?;
+        assert_eq!(jpeg.segments[2].data, exif);
+        assert!(jpeg.trailer.is_empty());
+
+        let jpeg = Jpeg::read_headers_only(&mut Cursor::new(&data))?;
+        let dhts = jpeg.segments.iter().filter(|s| s.marker == DHT);
+        assert_eq!(dhts.count(), 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_rare_markers() -> Result<()> {
+        use crate::processors::DhtWriter;
+
+        let mut data = TestImage::new(16, 16).build();
+        insert_segment(&mut data, 0xDB, 0, 0xCC, &[0x00, 0x10, 0x10, 0x05]);
+        insert_segment(&mut data, 0xDB, 0, 0xDF, &[0x11]);
+        insert_segment(&mut data, 0xDB, 0, 0xF7, &[0x01, 0x02, 0x03]);
+        let dqt = data
+            .windows(2)
+            .position(|bytes| bytes == [0xFF, 0xDB])
+            .unwrap();
+        data.splice(dqt..dqt, [0xFF, 0x01]);
+
+        let expected = [
+            SOI,
+            APP(0),
+            DAC,
+            EXP,
+            JPGn(7),
+            TEM,
+            DQT,
+            SOF0,
+            DHT,
+            SOS,
+            EOI,
+        ];
+        assert_eq!(markers(&data)?, expected);
+
+        let headers = Jpeg::read_headers_only(&mut Cursor::new(&data))?;
+        let header_markers = headers.segments.iter().map(|s| s.marker);
+        assert_eq!(
+            header_markers.collect::<Vec<_>>(),
+            expected[..expected.len() - 1]
+        );
+
+        // Passed through untouched
+        let mut output = Vec::new();
+        let mut jpeg = Jpeg::read_segments(&mut Cursor::new(&data))?;
+        jpeg.process_segments_mut(DhtWriter::new(&mut output, |_: &mut _| {}))?;
+        assert_eq!(output[..dqt + 2], data[..dqt + 2]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_should_continue() {
+        use std::sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        };
+
+        use crate::processors::DhtWriter;
+
+        let data = TestImage::new(64, 64).build();
+        let num_segments = markers(&data).unwrap().len();
+
+        // Stopping ahead of the first segment, then partway through the scan's rows
+        for checks_allowed in [0, num_segments + 2] {
+            let checks = Arc::new(AtomicUsize::new(0));
+            let mut jpeg = Jpeg::read_segments(&mut Cursor::new(&data)).unwrap();
+            jpeg.should_continue = Some(Box::new({
+                let checks = checks.clone();
+                move || checks.fetch_add(1, Ordering::Relaxed) < checks_allowed
+            }));
+
+            let writer = DhtWriter::new(Vec::new(), |_: &mut _| {}).always_reencode();
+            let error = jpeg.process_segments_mut(writer).unwrap_err();
+            assert_eq!(error.downcast_ref(), Some(&StegError::Cancelled));
+            assert_eq!(checks.load(Ordering::Relaxed), checks_allowed + 1);
+        }
+
+        let mut jpeg = Jpeg::read_segments(&mut Cursor::new(&data)).unwrap();
+        jpeg.should_continue = Some(Box::new(|| true));
+        let mut output = Vec::new();
+        jpeg.process_segments_mut(DhtWriter::new(&mut output, |_: &mut _| {}))
+            .unwrap();
+        assert_eq!(decode_pixels(&output), decode_pixels(&data));
+    }
+}
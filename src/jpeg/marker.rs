@@ -1,11 +1,15 @@
+use std::fmt;
+
 // [SPEC] Table B.1
 #[allow(clippy::upper_case_acronyms)]
-#[derive(Debug, Clone, Copy, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum Marker {
     SOF0,        // Baseline DCT
     SOF1,        // Extended Sequential DCT
     SOF2,        // Progressive DCT
     DHT,         // Huffman Table Specification
+    JPG,         // Reserved for JPEG extensions
+    DAC,         // Define Arithmetic Coding Conditioning(s)
     RST(u8),     // Restart markers
     SOI,         // Start of Image
     EOI,         // End of Image
@@ -13,9 +17,82 @@ pub enum Marker {
     DQT,         // Define Quantization Table(s)
     DNL,         // Define Number of Lines
     DRI,         // Define Restart Interval
+    DHP,         // Define Hierarchical Progression
+    EXP,         // Expand Reference Component(s)
+    APP(u8),     // Application segments
+    JPGn(u8),    // Reserved for JPEG extensions
+    COM,         // Comment
+    TEM,         // For temporary private use in arithmetic coding
     Unknown(u8), // Unknown / misc marker
 }
 
+impl Marker {
+    /// Whether the marker starts a segment with a length field, rather than standing alone
+    pub fn has_length(self) -> bool {
+        use Marker::*;
+
+        // [SPEC] B.1.1.3 -- TEM has no parameters
+        !matches!(self, SOI | EOI | RST(_) | TEM)
+    }
+}
+
+/// How the image data is coded, as given by the frame header's SOF marker
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CodingMode {
+    Baseline,
+    ExtendedSequential,
+    Progressive,
+    Arithmetic,
+    /// Lossless or hierarchical (differential) coding, with the SOF marker's second byte
+    Unsupported(u8),
+}
+
+impl CodingMode {
+    /// The mode a marker gives, if it's a frame header at all
+    pub fn from_marker(marker: Marker) -> Option<Self> {
+        use CodingMode::*;
+
+        // [SPEC] Table B.1 -- 0xC4, 0xC8 and 0xCC are DHT, JPG and DAC rather than SOF markers
+        match u8::from(marker) {
+            0xC0 => Some(Baseline),
+            0xC1 => Some(ExtendedSequential),
+            0xC2 => Some(Progressive),
+            0xC9..=0xCB | 0xCD..=0xCF => Some(Arithmetic),
+            value @ (0xC3 | 0xC5..=0xC7) => Some(Unsupported(value)),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Marker {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use Marker::*;
+
+        match self {
+            SOF0 => write!(f, "Baseline DCT frame"),
+            SOF1 => write!(f, "Extended sequential DCT frame"),
+            SOF2 => write!(f, "Progressive DCT frame"),
+            DHT => write!(f, "Define Huffman Table"),
+            JPG => write!(f, "JPEG extensions (reserved)"),
+            DAC => write!(f, "Define Arithmetic Coding Conditioning"),
+            RST(n) => write!(f, "Restart {n}"),
+            SOI => write!(f, "Start of Image"),
+            EOI => write!(f, "End of Image"),
+            SOS => write!(f, "Start of Scan"),
+            DQT => write!(f, "Define Quantization Table"),
+            DNL => write!(f, "Define Number of Lines"),
+            DRI => write!(f, "Define Restart Interval"),
+            DHP => write!(f, "Define Hierarchical Progression"),
+            EXP => write!(f, "Expand Reference Components"),
+            APP(n) => write!(f, "Application segment {n}"),
+            JPGn(n) => write!(f, "JPEG extension {n} (reserved)"),
+            COM => write!(f, "Comment"),
+            TEM => write!(f, "Temporary private use"),
+            Unknown(value) => write!(f, "Unknown marker 0x{value:02X}"),
+        }
+    }
+}
+
 impl From<u8> for Marker {
     fn from(value: u8) -> Self {
         use Marker::*;
@@ -25,6 +102,8 @@ impl From<u8> for Marker {
             0xC1 => SOF1,
             0xC2 => SOF2,
             0xC4 => DHT,
+            0xC8 => JPG,
+            0xCC => DAC,
             0xD0..=0xD7 => RST(value - 0xD0),
             0xD8 => SOI,
             0xD9 => EOI,
@@ -32,6 +111,12 @@ impl From<u8> for Marker {
             0xDB => DQT,
             0xDC => DNL,
             0xDD => DRI,
+            0xDE => DHP,
+            0xDF => EXP,
+            0xE0..=0xEF => APP(value - 0xE0),
+            0xF0..=0xFD => JPGn(value - 0xF0),
+            0xFE => COM,
+            0x01 => TEM,
             _ => Unknown(value),
         }
     }
@@ -46,6 +131,8 @@ impl From<Marker> for u8 {
             SOF1 => 0xC1,
             SOF2 => 0xC2,
             DHT => 0xC4,
+            JPG => 0xC8,
+            DAC => 0xCC,
             RST(value) => 0xD0 + value,
             SOI => 0xD8,
             EOI => 0xD9,
@@ -53,7 +140,51 @@ impl From<Marker> for u8 {
             DQT => 0xDB,
             DNL => 0xDC,
             DRI => 0xDD,
+            DHP => 0xDE,
+            EXP => 0xDF,
+            APP(value) => 0xE0 + value,
+            JPGn(value) => 0xF0 + value,
+            COM => 0xFE,
+            TEM => 0x01,
             Unknown(value) => value,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        assert_eq!(Marker::from(0xE1), Marker::APP(1));
+        assert_eq!(u8::from(Marker::APP(1)), 0xE1);
+        assert_eq!(Marker::from(0xFE), Marker::COM);
+
+        for value in 0..=0xFF {
+            assert_eq!(u8::from(Marker::from(value)), value);
+        }
+
+        assert!(Marker::APP(15).has_length());
+        assert!(Marker::COM.has_length());
+
+        assert_eq!(Marker::from(0xCC), Marker::DAC);
+        assert_eq!(Marker::from(0xDF), Marker::EXP);
+        assert_eq!(Marker::from(0xF7), Marker::JPGn(7));
+        assert!(Marker::DAC.has_length());
+        assert!(!Marker::TEM.has_length());
+    }
+
+    #[test]
+    fn test_display() {
+        assert_eq!(Marker::SOF0.to_string(), "Baseline DCT frame");
+        assert_eq!(Marker::DHT.to_string(), "Define Huffman Table");
+        assert_eq!(Marker::APP(1).to_string(), "Application segment 1");
+        assert_eq!(Marker::RST(7).to_string(), "Restart 7");
+        assert_eq!(
+            Marker::DAC.to_string(),
+            "Define Arithmetic Coding Conditioning"
+        );
+        assert_eq!(Marker::Unknown(0x02).to_string(), "Unknown marker 0x02");
+    }
+}
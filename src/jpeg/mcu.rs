@@ -0,0 +1,174 @@
+use anyhow::{anyhow, Result};
+
+use super::{
+    segments::{Component, SofData},
+    Jpeg, Marker,
+};
+
+/// The MCU grid of an interleaved scan over every component of the frame
+pub struct McuGeometry {
+    pub max_mcu_x: u32,
+    pub max_mcu_y: u32,
+    /// (h_factor, v_factor) of each frame component, in frame order
+    pub sampling_factors: Vec<(u32, u32)>,
+    /// The usual J:a:b name for the chroma subsampling, e.g. "4:2:0"
+    pub subsampling: String,
+}
+
+impl Jpeg {
+    pub fn mcu_geometry(&self) -> Result<McuGeometry> {
+        let segment = self
+            .segments
+            .iter()
+            .find(|segment| matches!(segment.marker, Marker::SOF0 | Marker::SOF1 | Marker::SOF2))
+            .ok_or_else(|| anyhow!("No frame header found"))?;
+        let frame = SofData::try_from(&segment.data[..])?;
+
+        let components = frame.components.iter().collect::<Vec<_>>();
+        let (max_mcu_x, max_mcu_y) = mcu_range(&frame, &components);
+        let sampling_factors = frame
+            .components
+            .iter()
+            .map(|component| (component.h_factor, component.v_factor))
+            .collect::<Vec<_>>();
+        let subsampling = subsampling_name(&sampling_factors);
+
+        Ok(McuGeometry {
+            max_mcu_x,
+            max_mcu_y,
+            sampling_factors,
+            subsampling,
+        })
+    }
+}
+
+/// Width and height in pixels of an MCU, for a scan of the given components
+pub fn mcu_size(components: &[&Component]) -> (u32, u32) {
+    let h_max = components.iter().map(|c| c.h_factor).max().unwrap();
+    let v_max = components.iter().map(|c| c.v_factor).max().unwrap();
+    (h_max * 8, v_max * 8)
+}
+
+/// Number of MCUs across and down the frame, for a scan of the given components
+pub fn mcu_range(frame: &SofData, components: &[&Component]) -> (u32, u32) {
+    let (mcu_width, mcu_height) = mcu_size(components);
+
+    (
+        frame.width.div_ceil(mcu_width),
+        frame.height.div_ceil(mcu_height),
+    )
+}
+
+/// Number of MCUs across and down a scan of the given components. A scan of a single component
+/// isn't interleaved, and each of its MCUs is one block of that component alone.
+pub fn scan_mcu_range(frame: &SofData, components: &[&Component]) -> (u32, u32) {
+    let component = match components {
+        [component] => component,
+        _ => return mcu_range(frame, components),
+    };
+
+    // [SPEC] A.1.1 -- A component's dimensions are the frame's, scaled by its sampling factors
+    let h_max = frame.components.iter().map(|c| c.h_factor).max().unwrap();
+    let v_max = frame.components.iter().map(|c| c.v_factor).max().unwrap();
+    let width = (frame.width * component.h_factor).div_ceil(h_max);
+    let height = (frame.height * component.v_factor).div_ceil(v_max);
+
+    // [SPEC] A.2.2 -- Non-interleaved order is simply every block, left to right, top to bottom
+    (width.div_ceil(8), height.div_ceil(8))
+}
+
+/// The horizontal and vertical block counts each component has in an MCU of a scan of them all,
+/// which for a single component is just the one block
+pub fn scan_num_samples(components: &[&Component]) -> (Vec<u32>, Vec<u32>) {
+    match components.len() {
+        1 => (vec![1], vec![1]),
+        _ => num_samples(components),
+    }
+}
+
+/// The horizontal and vertical block counts each component has in an MCU
+pub fn num_samples(components: &[&Component]) -> (Vec<u32>, Vec<u32>) {
+    let horizontal = components.iter().map(|c| c.h_factor).collect::<Vec<_>>();
+    let vertical = components.iter().map(|c| c.v_factor).collect::<Vec<_>>();
+    (horizontal, vertical)
+}
+
+fn subsampling_name(sampling_factors: &[(u32, u32)]) -> String {
+    let (&(luma_h, luma_v), chroma) = match sampling_factors.split_first() {
+        Some(factors) => factors,
+        None => return "unknown".into(),
+    };
+
+    if chroma.is_empty() {
+        return "4:0:0".into();
+    }
+
+    let (chroma_h, chroma_v) = chroma[0];
+    let uniform_chroma = chroma
+        .iter()
+        .all(|&factors| factors == (chroma_h, chroma_v));
+    if !uniform_chroma || luma_h % chroma_h != 0 || luma_v % chroma_v != 0 {
+        return "non-standard".into();
+    }
+
+    // J is 4 luma samples wide; a is the chroma samples in the first row, b in the second
+    let a = 4 * chroma_h / luma_h;
+    let b = match luma_v / chroma_v {
+        1 => a,
+        2 => 0,
+        _ => return "non-standard".into(),
+    };
+
+    match 4 % (luma_h / chroma_h) {
+        0 => format!("4:{a}:{b}"),
+        _ => "non-standard".into(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::jpeg::test_image::TestImage;
+
+    #[test]
+    fn test_mcu_geometry() -> Result<()> {
+        let geometry =
+            |image: TestImage| Jpeg::read_segments(&mut Cursor::new(image.build()))?.mcu_geometry();
+
+        let mcu = geometry(TestImage::new(37, 19).components(&[(1, 2, 2), (2, 1, 1), (3, 1, 1)]))?;
+        assert_eq!((mcu.max_mcu_x, mcu.max_mcu_y), (3, 2));
+        assert_eq!(mcu.sampling_factors, [(2, 2), (1, 1), (1, 1)]);
+        assert_eq!(mcu.subsampling, "4:2:0");
+
+        let mcu = geometry(TestImage::new(40, 24))?;
+        assert_eq!((mcu.max_mcu_x, mcu.max_mcu_y), (5, 3));
+        assert_eq!(mcu.subsampling, "4:4:4");
+
+        let mcu = geometry(TestImage::new(40, 24).components(&[(1, 1, 1)]))?;
+        assert_eq!(mcu.subsampling, "4:0:0");
+
+        // Each component scanned alone covers just its own blocks
+        let image = TestImage::new(37, 19).components(&[(1, 2, 2), (2, 1, 1), (3, 1, 1)]);
+        let frame = Jpeg::read_segments(&mut Cursor::new(image.build()))?
+            .segments
+            .iter()
+            .find(|segment| segment.marker == Marker::SOF0)
+            .map(|segment| SofData::try_from(&segment.data[..]))
+            .unwrap()?;
+        let luma = &frame.components[0];
+        let chroma = &frame.components[1];
+        assert_eq!(scan_mcu_range(&frame, &[luma]), (5, 3));
+        assert_eq!(scan_mcu_range(&frame, &[chroma]), (3, 2));
+        assert_eq!(scan_mcu_range(&frame, &[luma, chroma]), (3, 2));
+        assert_eq!(scan_num_samples(&[luma]), (vec![1], vec![1]));
+
+        assert_eq!(subsampling_name(&[(2, 1), (1, 1), (1, 1)]), "4:2:2");
+        assert_eq!(subsampling_name(&[(4, 1), (1, 1), (1, 1)]), "4:1:1");
+        assert_eq!(subsampling_name(&[(1, 2), (1, 1), (1, 1)]), "4:4:0");
+        assert_eq!(subsampling_name(&[(3, 1), (1, 1), (1, 1)]), "non-standard");
+        assert_eq!(subsampling_name(&[(2, 2), (1, 1), (2, 1)]), "non-standard");
+        Ok(())
+    }
+}
@@ -1,9 +1,16 @@
+mod default_tables;
 mod entropy_stream;
 #[allow(clippy::module_inception)]
 mod jpeg;
 mod marker;
+mod mcu;
 pub mod segments;
+#[cfg(test)]
+pub mod test_image;
 
-pub use entropy_stream::process_entropy_stream;
-pub use jpeg::{Jpeg, ProcessSegment, ProcessSegmentMut, Segment};
-pub use marker::Marker;
+pub use entropy_stream::{
+    process_entropy_stream, process_entropy_stream_into, CoefficientGrid, ComponentCoefficients,
+};
+pub use jpeg::{Jpeg, ParsedSegment, ProcessSegment, ProcessSegmentMut, Segment};
+pub use marker::{CodingMode, Marker};
+pub use mcu::McuGeometry;
@@ -64,16 +64,30 @@ impl TryFrom<&[u8]> for SofData {
 
 #[derive(Default)]
 pub struct QuantizationTable {
+    /// 0 for 8-bit values, 1 for 16-bit
     pub precision: u32,
     pub table_index: usize,
-    pub values: Vec<u8>,
+    /// The 64 values, in zigzag order
+    pub values: Vec<u16>,
+}
+
+impl QuantizationTable {
+    /// The bytes the table takes up in a DQT segment: its precision and index, then its values
+    pub fn encoded_len(&self) -> usize {
+        1 + 64 * (1 + self.precision as usize)
+    }
 }
 
 impl ToVec for QuantizationTable {
     fn to_vec(&self) -> Vec<u8> {
         let mut output = Vec::new();
         output.push(((self.precision as u8) << 4) | self.table_index as u8);
-        output.extend(&self.values);
+        for &value in &self.values {
+            match self.precision {
+                0 => output.push(value as u8),
+                _ => output.extend(value.to_be_bytes()),
+            }
+        }
         output
     }
 }
@@ -81,11 +95,34 @@ impl ToVec for QuantizationTable {
 impl TryFrom<&[u8]> for QuantizationTable {
     type Error = anyhow::Error;
 
+    // [SPEC] B.2.4.1 -- Pq is 0 for 8-bit values, 1 for 16-bit
     fn try_from(data: &[u8]) -> Result<Self, Self::Error> {
-        let pq_byte = data[0];
+        let pq_byte = match data.first() {
+            None => anyhow::bail!("Quantization table is empty"),
+            Some(&pq_byte) => pq_byte,
+        };
         let precision = pq_byte >> 4;
         let table_index = pq_byte & 0xF;
-        let values = data[1..65].to_vec();
+        if precision > 1 {
+            anyhow::bail!("Quantization table has invalid precision {precision}");
+        }
+
+        let value_len = 1 + precision as usize;
+        let data = &data[1..];
+        if data.len() < 64 * value_len {
+            anyhow::bail!(
+                "Quantization table needs {} bytes for its values, but only {} remain",
+                64 * value_len,
+                data.len()
+            );
+        }
+        let values = data[..64 * value_len]
+            .chunks(value_len)
+            .map(|value| match value {
+                &[byte] => byte as u16,
+                bytes => u16::from_be_bytes([bytes[0], bytes[1]]),
+            })
+            .collect();
 
         Ok(QuantizationTable {
             precision: precision as u32,
@@ -117,8 +154,9 @@ impl TryFrom<&[u8]> for DqtData {
         let mut tables = Vec::new();
 
         while !data.is_empty() {
-            tables.push(QuantizationTable::try_from(data)?);
-            data = &data[65..];
+            let table = QuantizationTable::try_from(data)?;
+            data = &data[table.encoded_len()..];
+            tables.push(table);
         }
 
         Ok(Self { tables })
@@ -147,6 +185,13 @@ impl TryFrom<&[u8]> for HuffmanTableData {
     type Error = anyhow::Error;
 
     fn try_from(mut data: &[u8]) -> Result<Self, Self::Error> {
+        if data.len() < 17 {
+            anyhow::bail!(
+                "Huffman table truncated: {} bytes for its header",
+                data.len()
+            );
+        }
+
         let table_info = data[0];
         let table_class = table_info >> 4;
         let table_index = table_info & 0xF;
@@ -156,6 +201,12 @@ impl TryFrom<&[u8]> for HuffmanTableData {
         let num_values = sizes.iter().map(|&v| v as usize).sum::<usize>();
 
         data = &data[16..];
+        if data.len() < num_values {
+            anyhow::bail!(
+                "Huffman table declares {num_values} values, but only {} bytes remain",
+                data.len()
+            );
+        }
         let values = data[0..num_values].to_vec();
 
         Ok(Self {
@@ -241,18 +292,41 @@ pub struct SosData {
     pub image_data: Vec<u8>,
 }
 
-impl ToVec for SosData {
-    fn to_vec(&self) -> Vec<u8> {
+impl SosData {
+    /// As `to_vec`, but an error for a spectral range or successive approximation that doesn't fit
+    /// the header's fields, rather than a corrupt header
+    pub fn try_to_vec(&self) -> anyhow::Result<Vec<u8>> {
+        // [SPEC] B.2.3 -- Ss and Se are zig-zag indices from 0 to 63, with Ss <= Se, and Ah and Al
+        // are 4 bits each. `spectral_end` is one past Se.
+        let (start, end) = (self.spectral_start, self.spectral_end);
+        if end == 0 || end > 64 || start >= end {
+            anyhow::bail!("Scan's spectral range {start}..{end} isn't within 0..64");
+        }
+        if self.approx_high > 0xF || self.approx_low > 0xF {
+            anyhow::bail!(
+                "Scan's successive approximation {}/{} doesn't fit in 4 bits each",
+                self.approx_high,
+                self.approx_low
+            );
+        }
+
         let mut output = Vec::new();
         output.push(self.components.len() as u8);
         for table in &self.components {
             output.extend(table.to_vec());
         }
-        output.push(self.spectral_start as u8);
-        output.push((self.spectral_end - 1) as u8);
+        output.push(start as u8);
+        output.push((end - 1) as u8);
         output.push(((self.approx_high as u8) << 4) | self.approx_low as u8);
         output.extend(&self.image_data);
-        output
+        Ok(output)
+    }
+}
+
+impl ToVec for SosData {
+    /// Panics where `try_to_vec` would return an error
+    fn to_vec(&self) -> Vec<u8> {
+        self.try_to_vec().unwrap()
     }
 }
 
@@ -260,7 +334,17 @@ impl TryFrom<&[u8]> for SosData {
     type Error = anyhow::Error;
 
     fn try_from(data: &[u8]) -> Result<Self, Self::Error> {
-        let num_components = data[0] as usize;
+        // [SPEC] B.2.3 -- Ns, then two bytes per component, then Ss, Se and Ah/Al
+        let num_components = match data.first() {
+            Some(&count) => count as usize,
+            None => anyhow::bail!("Scan header is empty"),
+        };
+        if data.len() < 1 + 2 * num_components + 3 {
+            anyhow::bail!(
+                "Scan header of {} bytes is too short for {num_components} components",
+                data.len()
+            );
+        }
 
         let data = &data[1..];
         let mut components = Vec::new();
@@ -316,3 +400,195 @@ impl TryFrom<&[u8]> for DriData {
         })
     }
 }
+
+/// The frame's height, for a file whose frame header gives it as 0 and only settles it after the
+/// first scan
+#[derive(Default)]
+pub struct DnlData {
+    pub lines: u32,
+}
+
+impl TryFrom<&[u8]> for DnlData {
+    type Error = anyhow::Error;
+
+    fn try_from(data: &[u8]) -> Result<Self, Self::Error> {
+        if data.len() != 2 {
+            anyhow::bail!("DNL segment has length {}, expected 2", data.len());
+        }
+        let lines = u16::from_be_bytes(data[0..2].try_into().unwrap());
+        Ok(Self {
+            lines: lines as u32,
+        })
+    }
+}
+
+/// The JFIF header carried in APP0. Files that tag APP0 with anything else (e.g. AVI1) don't parse
+/// as one.
+#[derive(Default)]
+pub struct Jfif {
+    /// Major version in the high byte, minor in the low, so 1.02 is 0x0102
+    pub version: u16,
+    /// 0 for no units (`x_density`:`y_density` is just the pixel aspect ratio), 1 for dots per
+    /// inch, 2 for dots per centimetre
+    pub units: u8,
+    pub x_density: u16,
+    pub y_density: u16,
+    pub thumbnail_width: u8,
+    pub thumbnail_height: u8,
+    /// RGB, 3 bytes per thumbnail pixel, kept as given
+    pub thumbnail: Vec<u8>,
+}
+
+impl ToVec for Jfif {
+    fn to_vec(&self) -> Vec<u8> {
+        let mut output = b"JFIF\0".to_vec();
+        output.extend(self.version.to_be_bytes());
+        output.push(self.units);
+        output.extend(self.x_density.to_be_bytes());
+        output.extend(self.y_density.to_be_bytes());
+        output.push(self.thumbnail_width);
+        output.push(self.thumbnail_height);
+        output.extend(&self.thumbnail);
+        output
+    }
+}
+
+impl TryFrom<&[u8]> for Jfif {
+    type Error = anyhow::Error;
+
+    fn try_from(data: &[u8]) -> Result<Self, Self::Error> {
+        // The identifier, then version, units, two densities and the thumbnail's dimensions
+        if !data.starts_with(b"JFIF\0") {
+            anyhow::bail!("APP0 segment isn't tagged JFIF");
+        }
+        if data.len() < 14 {
+            anyhow::bail!("JFIF header of {} bytes is too short", data.len());
+        }
+
+        Ok(Self {
+            version: u16::from_be_bytes(data[5..7].try_into().unwrap()),
+            units: data[7],
+            x_density: u16::from_be_bytes(data[8..10].try_into().unwrap()),
+            y_density: u16::from_be_bytes(data[10..12].try_into().unwrap()),
+            thumbnail_width: data[12],
+            thumbnail_height: data[13],
+            thumbnail: data[14..].to_vec(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dht_bounds() {
+        let table = HuffmanTableData {
+            table_class: 1,
+            table_index: 0,
+            sizes: vec![0, 2, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0],
+            values: vec![0x01, 0x02, 0x11],
+        };
+        let data = table.to_vec();
+        assert_eq!(
+            DhtData::try_from(&data[..]).unwrap().tables[0].values,
+            table.values
+        );
+
+        // Sizes promising more values than there are bytes, and a header cut short
+        assert!(DhtData::try_from(&data[..data.len() - 1]).is_err());
+        assert!(DhtData::try_from(&data[..10]).is_err());
+
+        let mut data = data;
+        data[2] = 200;
+        assert!(DhtData::try_from(&data[..]).is_err());
+    }
+
+    #[test]
+    fn test_dqt_precision() {
+        let table_8 = QuantizationTable {
+            precision: 0,
+            table_index: 0,
+            values: (1..=64).collect(),
+        };
+        let table_16 = QuantizationTable {
+            precision: 1,
+            table_index: 1,
+            values: (0..64).map(|value| 300 + value * 2).collect(),
+        };
+        let data = DqtData {
+            tables: vec![table_16, table_8],
+        }
+        .to_vec();
+        assert_eq!(data.len(), 129 + 65);
+
+        let tables = DqtData::try_from(&data[..]).unwrap().tables;
+        assert_eq!(tables.len(), 2);
+        assert_eq!((tables[0].precision, tables[0].table_index), (1, 1));
+        assert_eq!(tables[0].values[..3], [300, 302, 304]);
+        assert_eq!((tables[1].precision, tables[1].table_index), (0, 0));
+        assert_eq!(tables[1].values, (1..=64).collect::<Vec<_>>());
+        assert_eq!(DqtData { tables }.to_vec(), data);
+
+        // A table cut short, and a precision that's neither 8 nor 16 bits
+        assert!(DqtData::try_from(&data[..data.len() - 1]).is_err());
+        assert!(DqtData::try_from(&data[..100]).is_err());
+        let mut data = data;
+        data[0] = 0x21;
+        assert!(DqtData::try_from(&data[..]).is_err());
+    }
+
+    #[test]
+    fn test_sos_bounds() {
+        let data = [1, 1, 0x00, 0, 63, 0, 0xAB];
+        let scan = SosData::try_from(&data[..]).unwrap();
+        assert_eq!(scan.components.len(), 1);
+        assert_eq!(scan.image_data, [0xAB]);
+        assert!(SosData::try_from(&data[..6]).is_ok());
+
+        assert!(SosData::try_from(&data[..3]).is_err());
+        assert!(SosData::try_from(&data[..5]).is_err());
+        assert!(SosData::try_from(&[][..]).is_err());
+    }
+
+    #[test]
+    fn test_sos_to_vec() {
+        let data = [1, 1, 0x00, 0, 63, 0, 0xAB];
+        let mut scan = SosData::try_from(&data[..]).unwrap();
+        assert_eq!(scan.try_to_vec().unwrap(), data);
+
+        // A DC-only progressive scan, Ss = Se = 0
+        scan.spectral_end = 1;
+        assert_eq!(scan.try_to_vec().unwrap()[3..5], [0, 0]);
+
+        scan.spectral_end = 0;
+        assert!(scan.try_to_vec().is_err());
+        scan.spectral_end = 65;
+        assert!(scan.try_to_vec().is_err());
+
+        scan.spectral_start = 10;
+        scan.spectral_end = 10;
+        assert!(scan.try_to_vec().is_err());
+
+        scan.spectral_start = 0;
+        scan.spectral_end = 64;
+        scan.approx_low = 16;
+        assert!(scan.try_to_vec().is_err());
+    }
+
+    #[test]
+    fn test_jfif() {
+        let data = [
+            b'J', b'F', b'I', b'F', 0, 1, 2, 1, 0, 72, 0, 96, 1, 1, 1, 2, 3,
+        ];
+        let jfif = Jfif::try_from(&data[..]).unwrap();
+        assert_eq!(jfif.version, 0x0102);
+        assert_eq!(jfif.units, 1);
+        assert_eq!((jfif.x_density, jfif.y_density), (72, 96));
+        assert_eq!(jfif.thumbnail, [1, 2, 3]);
+        assert_eq!(jfif.to_vec(), data);
+
+        assert!(Jfif::try_from(&data[..13]).is_err());
+        assert!(Jfif::try_from(&b"AVI1\0\0\0\0\0\0\0\0\0\0"[..]).is_err());
+    }
+}
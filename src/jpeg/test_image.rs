@@ -0,0 +1,416 @@
+// Synthetic JPEGs for tests, encoded with the Annex K tables so every 8-bit file decodes with any
+// conforming decoder.
+
+use crate::huffman::construct_huffman_table;
+
+use super::{
+    default_tables::default_dht_data,
+    segments::{DhtData, ToVec},
+};
+
+pub struct TestImage {
+    width: u16,
+    height: u16,
+    components: Vec<(u8, u8, u8)>,
+    restart_interval: u16,
+    with_dht: bool,
+    progressive: bool,
+    non_interleaved: bool,
+    precision: u8,
+}
+
+impl TestImage {
+    pub fn new(width: u16, height: u16) -> Self {
+        Self {
+            width,
+            height,
+            components: vec![(1, 1, 1), (2, 1, 1), (3, 1, 1)],
+            restart_interval: 0,
+            with_dht: true,
+            progressive: false,
+            non_interleaved: false,
+            precision: 8,
+        }
+    }
+
+    /// (component id, h_factor, v_factor) for each frame component, in scan order
+    pub fn components(mut self, components: &[(u8, u8, u8)]) -> Self {
+        self.components = components.to_vec();
+        self
+    }
+
+    pub fn restart_interval(mut self, restart_interval: u16) -> Self {
+        self.restart_interval = restart_interval;
+        self
+    }
+
+    pub fn without_dht(mut self) -> Self {
+        self.with_dht = false;
+        self
+    }
+
+    /// A DC scan of every component, followed by an AC scan per component. AC table 0 is
+    /// redefined ahead of each AC scan, and used by all of them.
+    pub fn progressive(mut self) -> Self {
+        self.progressive = true;
+        self
+    }
+
+    /// A sequential scan of each component in turn, rather than one scan of them all
+    pub fn non_interleaved(mut self) -> Self {
+        self.non_interleaved = true;
+        self
+    }
+
+    /// An extended (SOF1) frame with 12-bit samples, whose DC tables cover the 16 difference
+    /// categories those need
+    pub fn twelve_bit(mut self) -> Self {
+        self.precision = 12;
+        self
+    }
+
+    pub fn build(&self) -> Vec<u8> {
+        let mut out = vec![0xFF, 0xD8];
+
+        // APP0: JFIF 1.1 with no density, or the AVI1 tag that marks an MJPEG frame relying on
+        // the default Huffman tables
+        let app0_tag = if self.with_dht { b"JFIF" } else { b"AVI1" };
+        let mut app0 = app0_tag.to_vec();
+        app0.extend([0, 1, 1, 0, 0, 1, 0, 1, 0, 0]);
+        segment(&mut out, 0xE0, &app0);
+
+        // DQT: two flat tables
+        let mut dqt = Vec::new();
+        for table_index in 0..2 {
+            dqt.push(table_index);
+            dqt.extend([1u8; 64]);
+        }
+        segment(&mut out, 0xDB, &dqt);
+
+        // SOF0 / SOF1 / SOF2
+        let mut sof = vec![self.precision];
+        sof.extend(self.height.to_be_bytes());
+        sof.extend(self.width.to_be_bytes());
+        sof.push(self.components.len() as u8);
+        for (index, &(id, h, v)) in self.components.iter().enumerate() {
+            sof.extend([id, (h << 4) | v, table_for(index) as u8]);
+        }
+        let sof_marker = match (self.progressive, self.precision) {
+            (true, _) => 0xC2,
+            (false, 8) => 0xC0,
+            (false, _) => 0xC1,
+        };
+        segment(&mut out, sof_marker, &sof);
+
+        if self.with_dht {
+            segment(&mut out, 0xC4, &self.dht_data().to_vec());
+        }
+
+        if self.restart_interval > 0 {
+            segment(&mut out, 0xDD, &self.restart_interval.to_be_bytes());
+        }
+
+        if self.progressive {
+            self.progressive_scans(&mut out);
+        } else {
+            self.baseline_scans(&mut out);
+        }
+
+        out.extend([0xFF, 0xD9]);
+        out
+    }
+
+    fn dht_data(&self) -> DhtData {
+        let mut dht_data = default_dht_data();
+        if self.precision > 8 {
+            for table in dht_data.tables.iter_mut().filter(|t| t.table_class == 0) {
+                table.sizes = vec![0, 0, 0, 15, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+                table.values = (0..16).collect();
+            }
+        }
+        dht_data
+    }
+
+    fn baseline_scans(&self, out: &mut Vec<u8>) {
+        let all_components = (0..self.components.len()).collect::<Vec<_>>();
+        let scans = match self.non_interleaved {
+            true => all_components.iter().map(|&index| vec![index]).collect(),
+            false => vec![all_components],
+        };
+
+        let mut rng = Lcg(1);
+        for components in scans {
+            self.baseline_scan(out, &components, &mut rng);
+        }
+    }
+
+    fn baseline_scan(&self, out: &mut Vec<u8>, components: &[usize], rng: &mut Lcg) {
+        segment(out, 0xDA, &self.scan_header(components, 0, 63));
+
+        let codes = Codes::new(&self.dht_data());
+        let dc_categories = self.precision as u32 + 4;
+        let mut writer = BitWriter::default();
+        for (mcu_index, blocks) in self.mcus(components).into_iter().enumerate() {
+            if self.restart_interval > 0
+                && mcu_index > 0
+                && mcu_index % self.restart_interval as usize == 0
+            {
+                writer.byte_align();
+                let restart = (mcu_index / self.restart_interval as usize - 1) % 8;
+                writer.bytes.extend([0xFF, 0xD0 + restart as u8]);
+            }
+
+            for component in blocks {
+                let table = table_for(component);
+                encode_dc(&mut writer, rng, &codes.dc[table], dc_categories);
+                encode_ac(&mut writer, rng, &codes.ac[table]);
+            }
+        }
+
+        writer.byte_align();
+        out.extend(writer.bytes);
+    }
+
+    fn progressive_scans(&self, out: &mut Vec<u8>) {
+        let codes = Codes::new(&self.dht_data());
+        let dc_categories = self.precision as u32 + 4;
+        let mut rng = Lcg(1);
+
+        // [SPEC] G.1.2.1 -- DC first scan, interleaved
+        let all_components = (0..self.components.len()).collect::<Vec<_>>();
+        segment(out, 0xDA, &self.scan_header(&all_components, 0, 0));
+
+        let mut writer = BitWriter::default();
+        for blocks in self.mcus(&all_components) {
+            for component in blocks {
+                encode_dc(
+                    &mut writer,
+                    &mut rng,
+                    &codes.dc[table_for(component)],
+                    dc_categories,
+                );
+            }
+        }
+        writer.byte_align();
+        out.extend(writer.bytes);
+
+        // [SPEC] G.1.2.2 -- AC first scans, one per component, each block being a lone EOB
+        for component in 0..self.components.len() {
+            let ac_table = default_dht_data().tables.remove(1);
+            let redefinition = DhtData {
+                tables: vec![ac_table],
+            };
+            segment(out, 0xC4, &redefinition.to_vec());
+
+            let mut header = self.scan_header(&[component], 1, 63);
+            header[2] = 0x00;
+            segment(out, 0xDA, &header);
+
+            let mut writer = BitWriter::default();
+            for _ in self.mcus(&[component]) {
+                writer.write_bits(&codes.ac[0][0x00]);
+            }
+            writer.byte_align();
+            out.extend(writer.bytes);
+        }
+    }
+
+    fn scan_header(&self, components: &[usize], spectral_start: u8, spectral_end: u8) -> Vec<u8> {
+        let mut sos = vec![components.len() as u8];
+        for &index in components {
+            let table = table_for(index) as u8;
+            sos.extend([self.components[index].0, (table << 4) | table]);
+        }
+        sos.extend([spectral_start, spectral_end, 0]);
+        sos
+    }
+
+    /// The component index of each block, grouped by MCU
+    fn mcus(&self, components: &[usize]) -> Vec<Vec<usize>> {
+        let h_max = self.components.iter().map(|c| c.1 as u32).max().unwrap();
+        let v_max = self.components.iter().map(|c| c.2 as u32).max().unwrap();
+
+        // [SPEC] A.2 -- Order of source image data encoding
+        if let [index] = *components {
+            let (_, h, v) = self.components[index];
+            let blocks_x = (self.width as u32 * h as u32).div_ceil(h_max).div_ceil(8);
+            let blocks_y = (self.height as u32 * v as u32).div_ceil(v_max).div_ceil(8);
+            return (0..blocks_x * blocks_y).map(|_| vec![index]).collect();
+        }
+
+        let mcus_x = (self.width as u32).div_ceil(8 * h_max);
+        let mcus_y = (self.height as u32).div_ceil(8 * v_max);
+        let blocks = components
+            .iter()
+            .flat_map(|&index| {
+                let (_, h, v) = self.components[index];
+                vec![index; h as usize * v as usize]
+            })
+            .collect::<Vec<_>>();
+        (0..mcus_x * mcus_y).map(|_| blocks.clone()).collect()
+    }
+}
+
+/// Inserts a segment ahead of the `occurrence`th (zero-based) `next_marker` in the file
+pub fn insert_segment(
+    data: &mut Vec<u8>,
+    next_marker: u8,
+    occurrence: usize,
+    marker: u8,
+    payload: &[u8],
+) {
+    let position = data
+        .windows(2)
+        .enumerate()
+        .filter(|(_, bytes)| bytes == &[0xFF, next_marker])
+        .nth(occurrence)
+        .map(|(position, _)| position)
+        .expect("Expected the marker to be present");
+
+    let mut bytes = Vec::new();
+    segment(&mut bytes, marker, payload);
+    data.splice(position..position, bytes);
+}
+
+pub fn decode_pixels(data: &[u8]) -> Vec<u8> {
+    jpeg_decoder::Decoder::new(data)
+        .decode()
+        .expect("Expected a decodable JPEG")
+}
+
+fn table_for(component_index: usize) -> usize {
+    usize::from(component_index > 0)
+}
+
+/// Appends a segment with the given marker, its length counting itself
+pub fn segment(out: &mut Vec<u8>, marker: u8, payload: &[u8]) {
+    out.extend([0xFF, marker]);
+    out.extend((payload.len() as u16 + 2).to_be_bytes());
+    out.extend(payload);
+}
+
+/// The code bits of each symbol in the tables, indexed by table index then symbol
+struct Codes {
+    dc: [Vec<Vec<u8>>; 2],
+    ac: [Vec<Vec<u8>>; 2],
+}
+
+impl Codes {
+    fn new(dht_data: &DhtData) -> Self {
+        let tables = &dht_data.tables;
+        let codes = |class: usize, index: usize| {
+            let table = tables
+                .iter()
+                .find(|t| t.table_class == class && t.table_index == index)
+                .unwrap();
+
+            let mut codes = vec![Vec::new(); 256];
+            for (value, bits) in construct_huffman_table(&table.sizes, &table.values)
+                .unwrap()
+                .into_iter()
+                .take(table.values.len())
+            {
+                codes[value as usize] = bits;
+            }
+            codes
+        };
+
+        Self {
+            dc: [codes(0, 0), codes(0, 1)],
+            ac: [codes(1, 0), codes(1, 1)],
+        }
+    }
+}
+
+// Symbols are spread over the whole of each table, so that any permutation of the tables shows
+// up in the stream
+
+fn encode_dc(writer: &mut BitWriter, rng: &mut Lcg, codes: &[Vec<u8>], categories: u32) {
+    let category = rng.next(categories);
+    writer.write_bits(&codes[category as usize]);
+    writer.write_value(rng.next(1 << category), category);
+}
+
+fn encode_ac(writer: &mut BitWriter, rng: &mut Lcg, codes: &[Vec<u8>]) {
+    let mut index = 1;
+    for _ in 0..rng.next(6) {
+        let run = rng.next(16);
+        let size = 1 + rng.next(10);
+        if index + run > 63 {
+            break;
+        }
+
+        writer.write_bits(&codes[((run << 4) | size) as usize]);
+        writer.write_value(rng.next(1 << size), size);
+        index += run + 1;
+    }
+
+    if index < 64 {
+        writer.write_bits(&codes[0x00]);
+    }
+}
+
+struct Lcg(u32);
+
+impl Lcg {
+    fn next(&mut self, bound: u32) -> u32 {
+        self.0 = self.0.wrapping_mul(1103515245).wrapping_add(12345);
+        (self.0 >> 16) % bound
+    }
+}
+
+#[derive(Default)]
+struct BitWriter {
+    bytes: Vec<u8>,
+    current: u8,
+    count: u8,
+}
+
+impl BitWriter {
+    fn write_bits(&mut self, bits: &[u8]) {
+        for &bit in bits {
+            self.current = (self.current << 1) | bit;
+            self.count += 1;
+            if self.count == 8 {
+                self.bytes.push(self.current);
+                if self.current == 0xFF {
+                    self.bytes.push(0x00);
+                }
+                self.current = 0;
+                self.count = 0;
+            }
+        }
+    }
+
+    fn write_value(&mut self, value: u32, size: u32) {
+        for shift in (0..size).rev() {
+            self.write_bits(&[((value >> shift) & 1) as u8]);
+        }
+    }
+
+    fn byte_align(&mut self) {
+        while self.count != 0 {
+            self.write_bits(&[1]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_image_decodes() {
+        for image in [
+            TestImage::new(40, 24),
+            TestImage::new(37, 19).components(&[(1, 2, 2), (2, 1, 1), (3, 1, 1)]),
+            TestImage::new(30, 30).components(&[(1, 1, 1)]),
+            TestImage::new(64, 16).restart_interval(3),
+            TestImage::new(16, 16).without_dht(),
+            TestImage::new(37, 19).progressive(),
+        ] {
+            let pixels = decode_pixels(&image.build());
+            assert!(!pixels.is_empty());
+        }
+    }
+}
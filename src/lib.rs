@@ -0,0 +1,30 @@
+pub mod analysis;
+pub mod app_payload;
+pub mod dqt_lsb;
+mod ecc;
+mod error;
+mod fns;
+mod huffman;
+pub mod jpeg;
+pub mod lib_secret;
+pub mod processors;
+mod rw_stream;
+
+pub use analysis::diff_offsets;
+pub use app_payload::{read_app_payload, write_app_payload};
+pub use dqt_lsb::{read_secret_dqt_lsb, write_secret_dqt_lsb};
+pub use ecc::EccParams;
+pub use error::StegError;
+pub use fns::{apply_permutation, split_table_values, split_table_values_mut};
+pub use jpeg::CodingMode;
+pub use lib_secret::{
+    capacity, check_fit, coding_mode, peek_secret_len, permutation_space, quick_detect,
+    read_huffman_tables, read_secret, read_secret_biguint, read_secret_detailed,
+    read_secret_from_mmap, read_secret_multi, read_secret_with_key, resave, sign_tables,
+    strip_secret, table_permutations, table_report, verify_integrity, write_secret,
+    write_secret_biguint, write_secret_into, write_secret_multi, write_secret_to_slice,
+    DetailedSecret, FitReport, PreparedCarrier, TableFillObserver, TableReport, WriteData,
+    WriteOptions,
+};
+#[cfg(feature = "std-fs")]
+pub use lib_secret::{capacity_histogram, FileCapacity};
@@ -1,91 +1,3070 @@
-use std::cell::RefCell;
-use std::io::{Read, Write};
+use std::collections::HashMap;
+use std::io::{Cursor, Read, Write};
+use std::sync::Arc;
 
 use anyhow::Result;
+use hmac::{Hmac, Mac};
 use num_bigint::BigUint;
+use num_traits::{ToPrimitive, Zero};
+use sha2::Sha256;
+use subtle::{Choice, ConstantTimeEq};
 
-use crate::jpeg::{segments::HuffmanTableData, Jpeg};
+use crate::jpeg::{
+    segments::{DhtData, HuffmanTableData},
+    CodingMode, Jpeg, Marker, Segment,
+};
 use crate::{
+    ecc::{self, EccParams},
     fns::{MaxBaseValue, TryFromInput, NS2},
-    processors::{DhtReader, DhtWriter},
+    huffman::check_huffman_table,
+    processors::DhtWriter,
+    StegError,
 };
 
+const SECRET_HEADER: [u8; 2] = [0xBE, 0xEF]; // A minimal safety header
+const FILLED_SECRET_HEADER: [u8; 2] = [0xBE, 0xF0]; // Followed by the secret's length, then fill
+const INTEGRITY_HEADER: [u8; 2] = [0xBE, 0xF2]; // Followed by the tables' truncated HMAC
+const INTEGRITY_TAG_LEN: usize = 16;
+const MULTI_PART_HEADER_LEN: usize = 4; // The part's index, then the number of parts
+const LOW_CAPACITY: usize = 16; // Bytes of capacity below which a carrier is flagged in `WriteData`
+
 pub struct WriteData {
     pub approx_max_size: usize,
     pub secret_size: usize,
+    /// How much of the permutation space the secret uses up, from 0.0 to 1.0. The higher it is,
+    /// the further the tables tend to stray from their original order.
+    pub fill_ratio: f64,
+    /// With `WriteOptions::ecc`, how many tables can be re-ordered (e.g. by a re-encoder) with the
+    /// secret still recoverable
+    pub lost_tables_tolerated: Option<usize>,
+    /// The carrier holds fewer than 16 bytes (as `capacity` counts with the options given), as is
+    /// typical of grayscale files with just two small tables
+    pub low_capacity: bool,
+}
+
+/// The outcome of `check_fit`, matching the `WriteData` that `write_secret` would return
+pub struct FitReport {
+    pub fits: bool,
+    pub approx_max_size: usize,
+    pub secret_size: usize,
+}
+
+/// How much one of the tables carrying the secret contributes to the capacity
+pub struct TableReport {
+    pub table_class: usize,
+    pub table_index: usize,
+    pub sizes: Vec<u8>,
+    /// Values that share their code length with others, and so can be re-arranged
+    pub usable_values: usize,
+    pub bits_of_capacity: u64,
 }
 
+/// The outcome of `read_secret_detailed`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DetailedSecret {
+    pub secret: Vec<u8>,
+    /// Whether the secret passed its checks, and so is what `read_secret` returns. When it's
+    /// false, `secret` holds the data shards of a secret written with `ecc` as they were found,
+    /// damage and all.
+    pub checksum_ok: bool,
+}
+
+#[derive(Default)]
+pub struct WriteOptions {
+    /// Give files without any DHT segments (e.g. MJPEG frames) explicit copies of the default
+    /// tables, so there's something to permute
+    pub inject_default_tables: bool,
+    /// Follow the secret with random bytes generated from this seed, using up the rest of the
+    /// capacity
+    pub fill_remaining: Option<u64>,
+    /// Carry a short secret in just the last few tables, leaving the rest in their original order
+    /// rather than re-arranging them into sorted order
+    pub minimize_reorder: bool,
+    /// Give the least significant part of the secret to the tables with the most capacity, so a
+    /// short secret leaves the smaller tables in sorted order. The order is worked out from the
+    /// tables' sizes alone, which the reader sees unchanged.
+    pub least_changed_first: bool,
+    /// Split the secret into shards with Reed-Solomon parity, each held by its own group of
+    /// tables, so it survives some of the tables being lost. The secret is laid out by shard, so
+    /// `fill_remaining`, `minimize_reorder` and `least_changed_first` don't apply.
+    pub ecc: Option<EccParams>,
+    /// Pad the output out to exactly this many bytes (e.g. the input's length, for systems that
+    /// expect it unchanged) with a comment segment ahead of EOI. Re-encoding the image data can
+    /// add or drop a few stuffed 0x00 bytes, so the write fails if the output comes out longer,
+    /// or short by less than the 4 bytes of an empty comment.
+    pub pad_to_len: Option<usize>,
+    /// Spread the secret over the tables in an order shuffled by this key, which
+    /// `read_secret_with_key` needs to find it again. This only obscures where the secret is:
+    /// with the key unknown, the secret itself is still stored in the clear. It takes the place of
+    /// `least_changed_first`, and can't be combined with `ecc`.
+    pub key: Option<Vec<u8>>,
+    /// Refuse carriers that hold fewer bytes than this (as `capacity` counts with these options)
+    /// with `StegError::LowCapacity`, whatever the secret's length
+    pub min_capacity: Option<usize>,
+    /// Called for each table the scans use, in file order, once its values are settled, with
+    /// its class, index, the bits the order of its values now holds and the most it could hold.
+    /// Meant for showing how full each table is.
+    pub on_table_fill: Option<TableFillObserver>,
+}
+
+/// See `WriteOptions::on_table_fill`
+pub type TableFillObserver = Arc<dyn Fn(usize, usize, u64, u64) + Send + Sync>;
+
 pub fn write_secret<R: Read, W: Write, T: AsRef<[u8]>>(
     reader: &mut R,
     writer: &mut W,
     secret: T,
+    options: &WriteOptions,
 ) -> Result<WriteData> {
-    let secret = secret.as_ref();
-    let mut jpeg = Jpeg::read_segments(reader)?;
-
-    let table_sizes = RefCell::new(Vec::new());
-    let table_values = RefCell::new(Vec::new());
-    jpeg.process_segments(DhtReader::new(|table: &HuffmanTableData| {
-        table_sizes.borrow_mut().push(table.sizes.clone());
-        table_values.borrow_mut().push(table.values.clone());
-    }))?;
+    PreparedCarrier::new(reader, options)?.write(writer, secret.as_ref())
+}
 
-    let table_sizes = table_sizes.into_inner();
-    let mut table_values = table_values.into_inner();
+/// As `write_secret`, for a file that's already been read
+pub fn write_secret_into<W: Write>(jpeg: Jpeg, writer: &mut W, secret: &[u8]) -> Result<WriteData> {
+    PreparedCarrier::from_jpeg(jpeg, &WriteOptions::default())?.write(writer, secret)
+}
 
-    let ns = {
-        let value = BigUint::from_bytes_be(&encode_secret(secret));
-        match NS2::try_from_input(value, &table_sizes) {
-            None => anyhow::bail!("Couldn't fit secret into image"),
-            Some(ns) => ns,
+/// Writes `value` into the tables as it is, without the header `write_secret` frames a secret in,
+/// for callers encoding their own data as a number. It must be less than the product of the
+/// tables' permutation counts.
+pub fn write_secret_biguint<R: Read, W: Write>(
+    reader: &mut R,
+    writer: &mut W,
+    value: &BigUint,
+) -> Result<WriteData> {
+    let carrier = PreparedCarrier::new(reader, &WriteOptions::default())?;
+    let ns = match NS2::try_from_input(value.clone(), &carrier.table_sizes) {
+        None => {
+            return Err(StegError::PayloadTooLarge {
+                attempted: value.to_bytes_be().len(),
+                max: (carrier.max_value.bits().saturating_sub(1) / 8) as usize,
+            }
+            .into())
         }
+        Some(ns) => ns,
     };
 
+    let mut table_values = carrier.table_values.clone();
     ns.permute_values(&mut table_values);
+    carrier.write_values(writer, table_values)?;
+
+    Ok(WriteData {
+        approx_max_size: carrier.max_value.to_bytes_be().len(),
+        secret_size: value.to_bytes_be().len(),
+        fill_ratio: ratio(value, &carrier.max_value),
+        lost_tables_tolerated: None,
+        low_capacity: carrier.capacity < LOW_CAPACITY,
+    })
+}
+
+/// As `write_secret`, writing into the caller's buffer rather than growing one. Returns the number
+/// of bytes written, or `StegError::BufferTooSmall` if the file wouldn't fit in `out`.
+pub fn write_secret_to_slice(input: &[u8], secret: &[u8], out: &mut [u8]) -> Result<usize> {
+    let mut writer = SliceWriter {
+        out,
+        position: 0,
+        overflowed: false,
+    };
+
+    match write_secret(
+        &mut Cursor::new(input),
+        &mut writer,
+        secret,
+        &WriteOptions::default(),
+    ) {
+        Ok(_) => Ok(writer.position),
+        Err(_) if writer.overflowed => Err(StegError::BufferTooSmall.into()),
+        Err(error) => Err(error),
+    }
+}
+
+/// Writes into a fixed buffer, refusing any write that would run past its end
+struct SliceWriter<'a> {
+    out: &'a mut [u8],
+    position: usize,
+    overflowed: bool,
+}
 
-    let table_index = RefCell::new(0usize);
-    jpeg.process_segments_mut(DhtWriter::new(writer, |table: &mut HuffmanTableData| {
-        let mut table_index = table_index.borrow_mut();
-        table.values = table_values[*table_index].clone();
-        *table_index += 1;
+impl Write for SliceWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let end = self.position + buf.len();
+        if end > self.out.len() {
+            self.overflowed = true;
+            return Err(std::io::ErrorKind::WriteZero.into());
+        }
+
+        self.out[self.position..end].copy_from_slice(buf);
+        self.position = end;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Re-encodes the file as embedding would, but with every table left as it is, as a baseline for
+/// what embedding itself changes
+pub fn resave<R: Read, W: Write>(reader: &mut R, writer: &mut W) -> Result<()> {
+    let mut jpeg = Jpeg::read_segments(reader)?;
+    jpeg.process_segments_mut(
+        DhtWriter::new(writer, |_: &mut HuffmanTableData| {}).always_reencode(),
+    )
+}
+
+/// Re-encodes the file with each table's values in ascending order within their code lengths,
+/// which holds no secret, so none can be read back afterwards. Returns whether any table was out
+/// of that order.
+pub fn strip_secret<R: Read, W: Write>(reader: &mut R, writer: &mut W) -> Result<bool> {
+    let mut jpeg = Jpeg::read_segments(reader)?;
+    let tables = read_tables(&jpeg)?;
+
+    let sorted_values = tables
+        .iter()
+        .map(|(table, _)| {
+            let mut values = table.values.clone();
+            let mut start = 0;
+            for &count in &table.sizes {
+                let end = (start + count as usize).min(values.len());
+                values[start..end].sort_unstable();
+                start = end;
+            }
+            values
+        })
+        .collect::<Vec<_>>();
+    let changed = tables
+        .iter()
+        .zip(&sorted_values)
+        .any(|((table, _), values)| &table.values != values);
+
+    jpeg.process_segments_mut(DhtWriter::new_batch(writer, |_: &[HuffmanTableData]| {
+        sorted_values.clone()
     }))?;
+    Ok(changed)
+}
 
-    let approx_max_size = table_sizes.max_base_value().to_bytes_be().len();
-    let secret_size = BigUint::from(ns).to_bytes_be().len();
+/// A carrier that's been parsed and had its tables read, ready to embed any number of secrets
+pub struct PreparedCarrier {
+    segments: Vec<Segment>,
+    trailer: Vec<u8>,
+    tables: Vec<(HuffmanTableData, bool)>,
+    table_sizes: Vec<Vec<u8>>,
+    table_values: Vec<Vec<u8>>,
+    max_value: BigUint,
+    capacity: usize,
+    fill_remaining: Option<u64>,
+    minimize_reorder: bool,
+    least_changed_first: bool,
+    ecc: Option<EccParams>,
+    pad_to_len: Option<usize>,
+    key: Option<Vec<u8>>,
+    output_restart_interval: Option<u32>,
+    on_table_fill: Option<TableFillObserver>,
+}
 
-    Ok(WriteData {
-        approx_max_size,
+impl PreparedCarrier {
+    pub fn new<R: Read>(reader: &mut R, options: &WriteOptions) -> Result<Self> {
+        let mut jpeg = Jpeg::read_segments(reader)?;
+
+        if options.inject_default_tables && !jpeg.has_huffman_tables() {
+            jpeg.inject_default_huffman_tables();
+        }
+
+        Self::from_jpeg(jpeg, options)
+    }
+
+    fn from_jpeg(jpeg: Jpeg, options: &WriteOptions) -> Result<Self> {
+        // Caught here rather than at the first scan, before any of the work of fitting the secret
+        if let Ok(CodingMode::Progressive) = jpeg.coding_mode() {
+            return Err(StegError::Progressive.into());
+        }
+
+        if options.ecc.is_some() && options.key.is_some() {
+            anyhow::bail!("A key can't be combined with ecc, which lays the secret out by shard");
+        }
+
+        let tables = read_tables(&jpeg)?;
+        for (table, _) in &tables {
+            if let Err(error) = check_huffman_table(&table.sizes, table.values.len()) {
+                let class = match table.table_class {
+                    0 => "DC",
+                    _ => "AC",
+                };
+                anyhow::bail!("{class} table {} is malformed: {error}", table.table_index);
+            }
+        }
+
+        let (table_sizes, table_values) = scan_table_data(&tables);
+        let max_value = table_sizes.max_base_value();
+        let capacity = max_secret_len(
+            &table_sizes,
+            options.fill_remaining.is_some(),
+            options.ecc.as_ref(),
+        );
+        if matches!(options.min_capacity, Some(min_capacity) if capacity < min_capacity) {
+            return Err(StegError::LowCapacity { capacity }.into());
+        }
+
+        Ok(Self {
+            segments: jpeg.segments,
+            trailer: jpeg.trailer,
+            tables,
+            table_sizes,
+            table_values,
+            max_value,
+            capacity,
+            fill_remaining: options.fill_remaining,
+            minimize_reorder: options.minimize_reorder,
+            least_changed_first: options.least_changed_first,
+            ecc: options.ecc,
+            pad_to_len: options.pad_to_len,
+            key: options.key.clone(),
+            output_restart_interval: jpeg.output_restart_interval,
+            on_table_fill: options.on_table_fill.clone(),
+        })
+    }
+
+    /// The carrier's bytes, with the secret written into its tables
+    pub fn embed<T: AsRef<[u8]>>(&self, secret: T) -> Result<Vec<u8>> {
+        let mut output = Vec::new();
+        self.write(&mut output, secret.as_ref())?;
+        Ok(output)
+    }
+
+    fn write<W: Write>(&self, writer: &mut W, secret: &[u8]) -> Result<WriteData> {
+        if let Some(ecc) = &self.ecc {
+            return self.write_ecc(writer, secret, ecc);
+        }
+
+        let value = match self.fill_remaining {
+            None => secret_value(secret),
+            Some(seed) => filled_secret_value(secret, seed, &self.max_value),
+        };
+
+        // The leading tables hold the most significant digits, so a short secret can leave them be
+        let order = usize::from(self.least_changed_first && self.key.is_none());
+        let permuted = match self.minimize_reorder {
+            false => self.permute_from(order, 0, &value),
+            true => (0..self.table_sizes.len())
+                .rev()
+                .find_map(|skip| self.permute_from(order, skip, &value)),
+        };
+
+        let (ns, table_values) = match permuted {
+            None => return Err(self.too_large(secret)),
+            Some(permuted) => permuted,
+        };
+
+        self.write_values(writer, table_values)?;
+
+        let value = BigUint::from(ns);
+
+        Ok(WriteData {
+            approx_max_size: self.max_value.to_bytes_be().len(),
+            secret_size: value.to_bytes_be().len(),
+            fill_ratio: ratio(&value, &self.max_value),
+            lost_tables_tolerated: None,
+            low_capacity: self.capacity < LOW_CAPACITY,
+        })
+    }
+
+    /// Each shard of the secret is written into its own group of tables, as given by `ecc_groups`
+    fn write_ecc<W: Write>(
+        &self,
+        writer: &mut W,
+        secret: &[u8],
+        ecc: &EccParams,
+    ) -> Result<WriteData> {
+        let groups = match ecc_groups(&self.table_sizes, ecc.total_shards()) {
+            None => anyhow::bail!(
+                "Can't spread {} shards over {} tables",
+                ecc.total_shards(),
+                self.table_sizes.len()
+            ),
+            Some(groups) => groups,
+        };
+        let records = ecc::shard_records(secret, ecc)?;
+
+        let mut table_values = self.table_values.clone();
+        let mut secret_size = 0;
+        let mut fill_ratio = 0f64;
+        for (group, record) in groups.iter().zip(&records) {
+            let sizes = reorder(&self.table_sizes, group);
+            let max_value = sizes.max_base_value();
+            let value = BigUint::from_bytes_be(record);
+
+            let ns = match NS2::try_from_input(value.clone(), &sizes) {
+                None => return Err(self.too_large(secret)),
+                Some(ns) => ns,
+            };
+
+            let mut values = reorder(&self.table_values, group);
+            ns.permute_values(&mut values);
+            for (&index, values) in group.iter().zip(values) {
+                table_values[index] = values;
+            }
+
+            secret_size += record.len();
+            fill_ratio = fill_ratio.max(ratio(&value, &max_value));
+        }
+
+        self.write_values(writer, table_values)?;
+
+        Ok(WriteData {
+            approx_max_size: self.max_value.to_bytes_be().len(),
+            secret_size,
+            fill_ratio,
+            lost_tables_tolerated: Some(ecc.parity_shards),
+            low_capacity: self.capacity < LOW_CAPACITY,
+        })
+    }
+
+    fn too_large(&self, secret: &[u8]) -> anyhow::Error {
+        let max = max_secret_len(
+            &self.table_sizes,
+            self.fill_remaining.is_some(),
+            self.ecc.as_ref(),
+        );
+        StegError::PayloadTooLarge {
+            attempted: secret.len(),
+            max,
+        }
+        .into()
+    }
+
+    /// Writes the carrier, with the tables carrying the secret given `table_values`
+    fn write_values<W: Write>(&self, writer: &mut W, table_values: Vec<Vec<u8>>) -> Result<()> {
+        let mut table_values = table_values.into_iter();
+        let new_values = self
+            .tables
+            .iter()
+            .map(|(table, in_scan)| match in_scan {
+                true => table_values.next().unwrap(),
+                false => table.values.clone(),
+            })
+            .collect::<Vec<_>>();
+
+        if let Some(on_table_fill) = &self.on_table_fill {
+            let tables = self.tables.iter().zip(&new_values);
+            for ((table, _), values) in tables.filter(|((_, in_scan), _)| *in_scan) {
+                let sizes = vec![table.sizes.clone()];
+                let used = NS2::read_value(&sizes, &vec![values.clone()]).bits();
+                let available = (sizes.max_base_value() - 1u8).bits();
+                on_table_fill(table.table_class, table.table_index, used, available);
+            }
+        }
+
+        let mut jpeg = Jpeg {
+            segments: self.segments.clone(),
+            trailer: self.trailer.clone(),
+            output_restart_interval: self.output_restart_interval,
+            ..Default::default()
+        };
+
+        let write_values = |_: &[HuffmanTableData]| new_values.clone();
+        let target_len = match self.pad_to_len {
+            None => return jpeg.process_segments_mut(DhtWriter::new_batch(writer, write_values)),
+            Some(target_len) => target_len,
+        };
+
+        let mut output = Vec::new();
+        jpeg.process_segments_mut(DhtWriter::new_batch(&mut output, write_values))?;
+
+        if output.len() != target_len {
+            let padding = match target_len.checked_sub(output.len() + 4) {
+                Some(padding) if padding <= u16::MAX as usize - 2 => padding,
+                _ => anyhow::bail!(
+                    "Can't pad the {} byte output to {target_len} bytes with a comment segment",
+                    output.len()
+                ),
+            };
+
+            let comment = Segment {
+                index: 0,
+                marker: Marker::COM,
+                data: vec![0; padding],
+            };
+            let mut bytes = Vec::new();
+            Jpeg::write_segment(&mut bytes, &comment)?;
+
+            let eoi_len = match self.segments.last() {
+                Some(segment) if segment.marker == Marker::EOI => 2,
+                _ => 0,
+            };
+            let position = output.len() - self.trailer.len() - eoi_len;
+            output.splice(position..position, bytes);
+        }
+
+        writer.write_all(&output)?;
+        Ok(())
+    }
+
+    /// Every table's values, with `value` written into those from `skip` onwards, taking the
+    /// tables in the `order`th of `table_orders`. The tables ahead of them are left as they are,
+    /// so long as nothing read ahead of the secret would be taken for the start of one.
+    fn permute_from(
+        &self,
+        order: usize,
+        skip: usize,
+        value: &BigUint,
+    ) -> Option<(NS2, Vec<Vec<u8>>)> {
+        let key = self.key.as_deref();
+        let indices = &table_orders(&self.table_sizes, key)[order];
+        let sizes = reorder(&self.table_sizes, indices);
+        let ns = NS2::try_from_input(value.clone(), &sizes[skip..].to_vec())?;
+
+        let mut values = reorder(&self.table_values, indices);
+        let mut permuted = values.split_off(skip);
+        ns.permute_values(&mut permuted);
+        values.extend(permuted);
+
+        let mut table_values = self.table_values.clone();
+        for (&index, values) in indices.iter().zip(values) {
+            table_values[index] = values;
+        }
+
+        let read_first = order * self.table_sizes.len() + skip;
+        let misread = candidate_values(&self.table_sizes, &table_values, key)
+            .take(read_first)
+            .any(|value| decode_secret(&value.to_bytes_be()).is_some());
+
+        match misread {
+            false => Some((ns, table_values)),
+            true => None,
+        }
+    }
+}
+
+/// Spreads one secret over several carriers, in order, for secrets too large for any one of them.
+/// Each carrier holds its part's index and the number of parts alongside it, so `read_secret_multi`
+/// can tell when a carrier is missing or out of place.
+pub fn write_secret_multi(
+    carriers: &mut [(&mut dyn Read, &mut dyn Write)],
+    secret: &[u8],
+) -> Result<Vec<WriteData>> {
+    let total = u16::try_from(carriers.len())
+        .map_err(|_| anyhow::anyhow!("Can't spread a secret over {} carriers", carriers.len()))?;
+
+    let prepared = carriers
+        .iter_mut()
+        .map(|(reader, _)| PreparedCarrier::new(reader, &WriteOptions::default()))
+        .collect::<Result<Vec<_>>>()?;
+
+    // Whole bytes only, below the maximum value, once the header and part numbers are in
+    let overhead = SECRET_HEADER.len() + MULTI_PART_HEADER_LEN;
+    let part_sizes = prepared
+        .iter()
+        .map(|carrier| (carrier.max_value.bits().saturating_sub(1) / 8) as usize)
+        .map(|max_len| max_len.saturating_sub(overhead))
+        .collect::<Vec<_>>();
+
+    let combined = part_sizes.iter().sum::<usize>();
+    if combined < secret.len() {
+        anyhow::bail!(
+            "Secret is {} bytes, but the carriers only hold {combined} between them",
+            secret.len()
+        );
+    }
+
+    let mut rest = secret;
+    let mut write_data = Vec::new();
+    for (index, ((carrier, (_, writer)), part_size)) in
+        prepared.iter().zip(carriers).zip(part_sizes).enumerate()
+    {
+        let (part, next) = rest.split_at(part_size.min(rest.len()));
+        rest = next;
+
+        let mut data = (index as u16).to_be_bytes().to_vec();
+        data.extend(total.to_be_bytes());
+        data.extend(part);
+        write_data.push(carrier.write(writer, &data)?);
+    }
+
+    Ok(write_data)
+}
+
+/// Reassembles a secret written by `write_secret_multi`, given the carriers in the same order
+pub fn read_secret_multi(readers: &mut [&mut dyn Read]) -> Result<Vec<u8>> {
+    let mut secret = Vec::new();
+    let mut expected_total = None;
+
+    for (position, reader) in readers.iter_mut().enumerate() {
+        let data = match read_secret(reader)? {
+            Some(data) if data.len() >= MULTI_PART_HEADER_LEN => data,
+            _ => anyhow::bail!("Carrier {position} doesn't hold part of a secret"),
+        };
+
+        let index = u16::from_be_bytes([data[0], data[1]]) as usize;
+        let total = u16::from_be_bytes([data[2], data[3]]) as usize;
+        let expected = *expected_total.get_or_insert(total);
+        if total != expected {
+            anyhow::bail!(
+                "Carrier {position} belongs to a set of {total} carriers, not {expected}"
+            );
+        }
+        if index != position {
+            anyhow::bail!(
+                "Carrier {position} holds part {index} of {total}; is one missing or out of order?"
+            );
+        }
+
+        secret.extend(&data[MULTI_PART_HEADER_LEN..]);
+    }
+
+    match expected_total {
+        Some(total) if total == readers.len() => Ok(secret),
+        Some(total) => anyhow::bail!("Only {} of the {total} carriers were given", readers.len()),
+        None => anyhow::bail!("No carriers were given"),
+    }
+}
+
+/// Stores a keyed HMAC of the tables' contents in their order, so that `verify_integrity` can later
+/// tell whether they've been re-ordered or replaced. Nothing is hidden: the tag is only for
+/// detecting tampering.
+pub fn sign_tables<R: Read, W: Write>(reader: &mut R, writer: &mut W, key: &[u8]) -> Result<()> {
+    let carrier = PreparedCarrier::new(reader, &WriteOptions::default())?;
+    let record = integrity_record(&carrier.table_sizes, &carrier.table_values, key);
+
+    match carrier.permute_from(0, 0, &BigUint::from_bytes_be(&record)) {
+        None => anyhow::bail!("The tables are too small to hold an integrity tag"),
+        Some((_, table_values)) => carrier.write_values(writer, table_values),
+    }
+}
+
+/// Whether the tables are still in the order `sign_tables` left them in, with the same key
+pub fn verify_integrity<R: Read>(reader: &mut R, key: &[u8]) -> Result<bool> {
+    let jpeg = Jpeg::read_headers_only(reader)?;
+    let (table_sizes, table_values) = scan_table_data(&read_tables(&jpeg)?);
+
+    let expected = integrity_record(&table_sizes, &table_values, key);
+    let value = NS2::read_value(&table_sizes, &table_values).to_bytes_be();
+    Ok(bool::from(value.ct_eq(&expected)))
+}
+
+/// HMAC-SHA-256, for the integrity tag and for `keyed_order`
+fn hmac_sha256(key: &[u8], message: &[u8]) -> [u8; 32] {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC takes keys of any length");
+    mac.update(message);
+    mac.finalize().into_bytes().into()
+}
+
+/// The header and tag `sign_tables` writes. The tag covers each table's code lengths and its
+/// values sorted within each length, which re-ordering them leaves unchanged.
+fn integrity_record(table_sizes: &[Vec<u8>], table_values: &[Vec<u8>], key: &[u8]) -> Vec<u8> {
+    let mut message = Vec::new();
+    for (sizes, values) in table_sizes.iter().zip(table_values) {
+        message.extend(sizes);
+
+        let mut start = 0;
+        for &count in sizes {
+            let mut bucket = values[start..start + count as usize].to_vec();
+            bucket.sort_unstable();
+            message.extend(bucket);
+            start += count as usize;
+        }
+    }
+
+    let mut record = INTEGRITY_HEADER.to_vec();
+    record.extend(&hmac_sha256(key, &message)[..INTEGRITY_TAG_LEN]);
+    record
+}
+
+/// Runs the same fit check as `write_secret` with default options, without re-encoding or writing
+/// anything. A carrier `write_secret` turns down before fitting the secret at all, such as a
+/// progressive file or one with a malformed table, fits nothing.
+pub fn check_fit<R: Read, T: AsRef<[u8]>>(reader: &mut R, secret: T) -> Result<FitReport> {
+    let jpeg = Jpeg::read_headers_only(reader)?;
+    let value = secret_value(secret.as_ref());
+    let secret_size = value.to_bytes_be().len();
+
+    let carrier = match PreparedCarrier::from_jpeg(jpeg, &WriteOptions::default()) {
+        Ok(carrier) => carrier,
+        Err(_) => {
+            return Ok(FitReport {
+                fits: false,
+                approx_max_size: 0,
+                secret_size,
+            })
+        }
+    };
+
+    Ok(FitReport {
+        fits: carrier.permute_from(0, 0, &value).is_some(),
+        approx_max_size: carrier.max_value.to_bytes_be().len(),
         secret_size,
     })
 }
 
+/// The longest secret `write_secret` accepts with these options, whatever its contents
+pub fn capacity<R: Read>(reader: &mut R, options: &WriteOptions) -> Result<usize> {
+    let jpeg = Jpeg::read_headers_only(reader)?;
+    let (table_sizes, _) = scan_table_data(&read_tables(&jpeg)?);
+    Ok(max_secret_len(
+        &table_sizes,
+        options.fill_remaining.is_some(),
+        options.ecc.as_ref(),
+    ))
+}
+
+/// One file's result from `capacity_histogram`
+#[cfg(feature = "std-fs")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileCapacity {
+    pub path: std::path::PathBuf,
+    /// As `capacity` gives with the default options, or 0 if there was an error
+    pub capacity: usize,
+    /// Why the file couldn't be read, if it couldn't
+    pub error: Option<String>,
+}
+
+/// The `capacity` of each file, in order, for seeing how it varies across a set of images. A file
+/// that can't be read or isn't supported is recorded with its error rather than ending the batch.
+#[cfg(feature = "std-fs")]
+pub fn capacity_histogram(paths: &[std::path::PathBuf]) -> Vec<FileCapacity> {
+    paths
+        .iter()
+        .map(|path| {
+            let result = std::fs::read(path)
+                .map_err(anyhow::Error::from)
+                .and_then(|data| capacity(&mut Cursor::new(data), &WriteOptions::default()));
+
+            let (capacity, error) = match result {
+                Ok(capacity) => (capacity, None),
+                Err(error) => (0, Some(error.to_string())),
+            };
+            FileCapacity {
+                path: path.clone(),
+                capacity,
+                error,
+            }
+        })
+        .collect()
+}
+
+/// The longest secret that fits however its bytes are set, found by trying the largest value a
+/// secret of each length can give (all of its bytes 0xFF) from the longest that could possibly fit
+fn max_secret_len(table_sizes: &[Vec<u8>], filled: bool, ecc: Option<&EccParams>) -> usize {
+    let max_value = table_sizes.to_vec().max_base_value();
+    let max_value_len = max_value.to_bytes_be().len();
+
+    if let Some(ecc) = ecc {
+        // The parity and checksum bytes could be anything, so each record has to fit in whole
+        // bytes below its group's maximum value
+        let group_bits = ecc_groups(table_sizes, ecc.total_shards()).and_then(|groups| {
+            let bits = groups
+                .iter()
+                .map(|group| reorder(table_sizes, group).max_base_value().bits());
+            bits.min()
+        });
+        let record_len = group_bits.map_or(0, |bits| (bits.saturating_sub(1) / 8) as usize);
+        return record_len.saturating_sub(ecc::SHARD_OVERHEAD) * ecc.data_shards;
+    }
+
+    let header_len = match filled {
+        false => SECRET_HEADER.len(),
+        true => FILLED_SECRET_HEADER.len() + 4,
+    };
+    (0..=max_value_len.saturating_sub(header_len))
+        .rev()
+        .find(|&len| {
+            let secret = vec![0xFF; len];
+            let value = match filled {
+                false => secret_value(&secret),
+                true => filled_secret_value(&secret, 0, &max_value),
+            };
+            value < max_value
+        })
+        .unwrap_or(0)
+}
+
+/// How the image data is coded, so callers can tell ahead of time whether an embed can work
+pub fn coding_mode<R: Read>(reader: &mut R) -> Result<CodingMode> {
+    Jpeg::read_headers_only(reader)?.coding_mode()
+}
+
+/// A breakdown of `capacity` by table, to show why an image holds as much (or as little) as it does
+pub fn table_report<R: Read>(reader: &mut R) -> Result<Vec<TableReport>> {
+    let jpeg = Jpeg::read_headers_only(reader)?;
+    let reports = read_tables(&jpeg)?
+        .into_iter()
+        .filter(|(_, in_scan)| *in_scan)
+        .map(|(table, _)| {
+            let usable_values = table
+                .sizes
+                .iter()
+                .filter(|&&count| count > 1)
+                .map(|&count| count as usize)
+                .sum();
+            let bits_of_capacity = table.sizes.max_base_value().bits().saturating_sub(1);
+
+            TableReport {
+                table_class: table.table_class,
+                table_index: table.table_index,
+                sizes: table.sizes,
+                usable_values,
+                bits_of_capacity,
+            }
+        })
+        .collect();
+
+    Ok(reports)
+}
+
+/// How each table carrying the secret has been re-arranged: for each of its values, the position
+/// that value would have if the values of each code length were in sorted order. An image that
+/// hasn't been touched (by this or by anything that sorts its tables) gives the identity.
+pub fn table_permutations<R: Read>(reader: &mut R) -> Result<Vec<Vec<usize>>> {
+    let jpeg = Jpeg::read_headers_only(reader)?;
+    let (table_sizes, table_values) = scan_table_data(&read_tables(&jpeg)?);
+
+    let permutations = table_sizes
+        .into_iter()
+        .zip(table_values)
+        .map(|(sizes, values)| {
+            NS2::read_values(&vec![sizes], &vec![values])
+                .to_permutations()
+                .pop()
+                .unwrap_or_default()
+        })
+        .collect();
+
+    Ok(permutations)
+}
+
+/// The number of ways a single table's values can be arranged without changing the image: the
+/// values sharing a code length can go in any order, so each length's `count` contributes
+/// `count!`. `sizes` is the table's count of codes of each length, as in its DHT segment.
+pub fn permutation_space(sizes: &[usize]) -> BigUint {
+    sizes
+        .iter()
+        .filter(|&&count| count > 1)
+        .map(|count| count.max_base_value())
+        .product()
+}
+
+/// Every Huffman table defined in the file, in the order they're defined, whether or not a scan
+/// decodes with it
+pub fn read_huffman_tables<R: Read>(reader: &mut R) -> Result<Vec<HuffmanTableData>> {
+    let jpeg = Jpeg::read_headers_only(reader)?;
+    Ok(read_tables(&jpeg)?
+        .into_iter()
+        .map(|(table, _)| table)
+        .collect())
+}
+
+/// Every Huffman table definition in the file, flagged with whether any scan is decoded with it.
+/// Only flagged tables carry the secret: a definition that's replaced before a scan starts (as
+/// progressive files may do for the same class and index) would just inflate the capacity.
+/// Writing and reading both go by this, so they agree on which tables hold what.
+fn read_tables(jpeg: &Jpeg) -> Result<Vec<(HuffmanTableData, bool)>> {
+    let mut tables = Vec::new();
+    let mut current_tables = HashMap::new();
+
+    for segment in &jpeg.segments {
+        match segment.marker {
+            Marker::DHT => {
+                for table in DhtData::try_from(&segment.data[..])?.tables {
+                    let key = (table.table_class, table.table_index);
+                    current_tables.insert(key, tables.len());
+                    tables.push((table, false));
+                }
+            }
+            Marker::SOS => {
+                for &index in current_tables.values() {
+                    tables[index].1 = true;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    Ok(tables)
+}
+
+fn scan_table_data(tables: &[(HuffmanTableData, bool)]) -> (Vec<Vec<u8>>, Vec<Vec<u8>>) {
+    tables
+        .iter()
+        .filter(|(_, in_scan)| *in_scan)
+        .map(|(table, _)| (table.sizes.clone(), table.values.clone()))
+        .unzip()
+}
+
+/// `numerator / denominator`, for values too large to convert to floats directly. Each is cut
+/// down to its top 64 bits (already more precision than an f64 holds), with the bits dropped
+/// restored as a power of two afterwards.
+fn ratio(numerator: &BigUint, denominator: &BigUint) -> f64 {
+    if denominator.is_zero() {
+        return 0.0;
+    }
+
+    let top_bits = |value: &BigUint| {
+        let shift = value.bits().saturating_sub(64);
+        let top = (value >> shift).to_u64().unwrap_or_default() as f64;
+        (top, shift as i32)
+    };
+
+    let (numerator, numerator_shift) = top_bits(numerator);
+    let (denominator, denominator_shift) = top_bits(denominator);
+    numerator / denominator * 2f64.powi(numerator_shift - denominator_shift)
+}
+
+fn secret_value(secret: &[u8]) -> BigUint {
+    BigUint::from_bytes_be(&encode_secret(secret))
+}
+
+// A short secret only ever picks from the first few permutations, which leave most of the values
+// in their original order -- itself a tell. Filling the rest of the capacity with seeded noise
+// makes the permutation index look uniformly chosen, whatever the length of the secret.
+fn filled_secret_value(secret: &[u8], seed: u64, max_value: &BigUint) -> BigUint {
+    let mut data = FILLED_SECRET_HEADER.to_vec();
+    data.extend((secret.len() as u32).to_be_bytes());
+    data.extend(secret);
+
+    // Whole bytes only, staying below the maximum value
+    let max_len = (max_value.bits().saturating_sub(1) / 8) as usize;
+    let mut rng = SplitMix64(seed);
+    while data.len() < max_len {
+        data.extend(rng.next().to_be_bytes());
+    }
+    data.truncate(max_len.max(FILLED_SECRET_HEADER.len() + 4 + secret.len()));
+
+    BigUint::from_bytes_be(&data)
+}
+
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E3779B97F4A7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+        z ^ (z >> 31)
+    }
+}
+
+// The header's leading 0xBE also keeps any leading zeros of the secret from being dropped by its
+// trip through BigUint
 fn encode_secret(secret: &[u8]) -> Vec<u8> {
-    let mut output = Vec::new();
-    output.push(0xBE); // A minimal safety header
-    output.push(0xEF);
+    let mut output = SECRET_HEADER.to_vec();
     output.extend(secret);
     output
 }
 
+/// Only the tables are read, never the image data, so this works for any coding mode and restart
+/// layout, including the progressive files `write_secret` turns down. The same goes for `capacity`
+/// and `table_report`.
 pub fn read_secret<R: Read>(reader: &mut R) -> Result<Option<Vec<u8>>> {
-    let jpeg = Jpeg::read_segments(reader)?;
+    secret_of(&Jpeg::read_headers_only(reader)?, None)
+}
 
-    let table_sizes = RefCell::new(Vec::new());
-    let table_values = RefCell::new(Vec::new());
-    jpeg.process_segments(DhtReader::new(|table: &HuffmanTableData| {
-        table_sizes.borrow_mut().push(table.sizes.clone());
-        table_values.borrow_mut().push(table.values.clone());
-    }))?;
+/// The value the tables hold, as `write_secret_biguint` wrote it. Any ordering of the tables
+/// gives some value, so this is only None for a file without any tables in use.
+pub fn read_secret_biguint<R: Read>(reader: &mut R) -> Result<Option<BigUint>> {
+    let jpeg = Jpeg::read_headers_only(reader)?;
+    let (table_sizes, table_values) = scan_table_data(&read_tables(&jpeg)?);
+    if table_sizes.is_empty() {
+        return Ok(None);
+    }
+
+    Ok(Some(NS2::read_value(&table_sizes, &table_values)))
+}
 
-    let table_sizes = table_sizes.into_inner();
-    let table_values = table_values.into_inner();
+/// As `read_secret`, for a secret written with `WriteOptions::key`
+pub fn read_secret_with_key<R: Read>(reader: &mut R, key: &[u8]) -> Result<Option<Vec<u8>>> {
+    secret_of(&Jpeg::read_headers_only(reader)?, Some(key))
+}
 
-    let ns = NS2::read_values(&table_sizes, &table_values);
-    let data = num_bigint::BigUint::from(ns).to_bytes_be();
+fn secret_of(jpeg: &Jpeg, key: Option<&[u8]>) -> Result<Option<Vec<u8>>> {
+    let (table_sizes, table_values) = scan_table_data(&read_tables(jpeg)?);
 
-    if data.len() <= 2 || data[0] != 0xBE || data[1] != 0xEF {
-        return Ok(None);
+    // Every candidate is decoded, so finding the secret early doesn't show in the timing
+    let secrets = candidate_values(&table_sizes, &table_values, key)
+        .map(|value| decode_secret(&value.to_bytes_be()))
+        .collect::<Vec<_>>();
+    if key.is_some() {
+        return Ok(secrets.into_iter().flatten().next());
+    }
+
+    // Each shard of a secret written with `ecc` has its own checksum, a far stronger check than
+    // the plain header, so it's trusted first
+    Ok(ecc_secret(&table_sizes, &table_values).or_else(|| secrets.into_iter().flatten().next()))
+}
+
+/// As `read_secret`, but a secret written with `ecc` that's too damaged to recover is still
+/// returned, for seeing how much of it survived
+pub fn read_secret_detailed<R: Read>(reader: &mut R) -> Result<Option<DetailedSecret>> {
+    let jpeg = Jpeg::read_headers_only(reader)?;
+    if let Some(secret) = secret_of(&jpeg, None)? {
+        return Ok(Some(DetailedSecret {
+            secret,
+            checksum_ok: true,
+        }));
+    }
+
+    let (table_sizes, table_values) = scan_table_data(&read_tables(&jpeg)?);
+    let damaged = (1..=table_sizes.len()).find_map(|total_shards| {
+        let records = ecc_records(&table_sizes, &table_values, total_shards)?;
+        ecc::recover_unchecked(records, total_shards)
+    });
+
+    Ok(damaged.map(|secret| DetailedSecret {
+        secret,
+        checksum_ok: false,
+    }))
+}
+
+/// As `read_secret`, for a file that's already in memory (e.g. memory-mapped). The file isn't
+/// copied into a buffer of its own, but the segments ahead of the first scan are each still copied
+/// out of `data`, as `Jpeg::headers_from_bytes` does.
+pub fn read_secret_from_mmap(data: &[u8]) -> Result<Option<Vec<u8>>> {
+    secret_of(&Jpeg::headers_from_bytes(data)?, None)
+}
+
+fn decode_secret(data: &[u8]) -> Option<Vec<u8>> {
+    // Both headers are compared before either is acted on, so a match doesn't cut the work short
+    let plain = has_header(data, &SECRET_HEADER);
+    let filled = has_header(data, &FILLED_SECRET_HEADER);
+    secret_after_header(data, plain, filled)
+}
+
+/// The secret in `data`, going by which of the two headers it was found to start with
+fn secret_after_header(data: &[u8], plain: Choice, filled: Choice) -> Option<Vec<u8>> {
+    if bool::from(plain) {
+        return Some(data[SECRET_HEADER.len()..].to_vec());
+    }
+
+    if bool::from(filled) {
+        let body = &data[FILLED_SECRET_HEADER.len()..];
+        if let Some(length) = body.get(..4) {
+            let length = u32::from_be_bytes(length.try_into().unwrap()) as usize;
+            return body[4..].get(..length).map(|secret| secret.to_vec());
+        }
+    }
+
+    None
+}
+
+/// Whether the file looks to carry a secret, for sweeping through many files. Like `read_secret`
+/// it never decodes the image data, but it stops at the first value the tables could hold that
+/// starts with a secret's header (or a shard's, for one written with `ecc`), and copies no secret
+/// out. Secrets written with a key aren't looked for.
+pub fn quick_detect(data: &[u8]) -> Result<bool> {
+    let jpeg = Jpeg::headers_from_bytes(data)?;
+    let (table_sizes, table_values) = scan_table_data(&read_tables(&jpeg)?);
+
+    if candidate_values(&table_sizes, &table_values, None)
+        .any(|value| peek_value_len(value).is_some())
+    {
+        return Ok(true);
+    }
+    Ok((1..=table_sizes.len()).any(|total_shards| {
+        let groups = ecc_groups(&table_sizes, total_shards).unwrap_or_default();
+        !groups.is_empty()
+            && groups.iter().all(|group| {
+                let sizes = reorder(&table_sizes, group);
+                let values = reorder(&table_values, group);
+                let record = NS2::read_value(&sizes, &values).to_bytes_be();
+                ecc::has_shard_header(&record)
+            })
+    }))
+}
+
+/// The length of the secret `read_secret` would return, found from just the leading bytes of the
+/// value the tables hold
+pub fn peek_secret_len<R: Read>(reader: &mut R) -> Result<Option<usize>> {
+    let jpeg = Jpeg::read_headers_only(reader)?;
+    let (table_sizes, table_values) = scan_table_data(&read_tables(&jpeg)?);
+
+    Ok(match ecc_secret(&table_sizes, &table_values) {
+        Some(secret) => Some(secret.len()),
+        None => candidate_values(&table_sizes, &table_values, None).find_map(peek_value_len),
+    })
+}
+
+fn peek_value_len(value: BigUint) -> Option<usize> {
+    let value_len = value.bits().div_ceil(8) as usize;
+    let prefix_len = FILLED_SECRET_HEADER.len() + 4;
+    let prefix = (value >> (8 * value_len.saturating_sub(prefix_len))).to_bytes_be();
+
+    if bool::from(has_header(&prefix, &SECRET_HEADER)) {
+        return Some(value_len - SECRET_HEADER.len());
+    }
+
+    if bool::from(has_header(&prefix, &FILLED_SECRET_HEADER)) && prefix.len() == prefix_len {
+        let length = u32::from_be_bytes(prefix[2..].try_into().unwrap()) as usize;
+        if length <= value_len - prefix_len {
+            return Some(length);
+        }
+    }
+
+    None
+}
+
+/// The value held by the tables, followed by the values held by all but the first table, all but
+/// the first two, and so on -- a secret written with `minimize_reorder` leaves the tables ahead of
+/// it in whatever order they started in. Then the same again, with the tables taken in
+/// `least_changed_first` order -- or, given a key, only in the order it shuffles them into.
+fn candidate_values<'a>(
+    table_sizes: &'a [Vec<u8>],
+    table_values: &'a [Vec<u8>],
+    key: Option<&[u8]>,
+) -> impl Iterator<Item = BigUint> + 'a {
+    table_orders(table_sizes, key)
+        .into_iter()
+        .flat_map(move |indices| {
+            let sizes = reorder(table_sizes, &indices);
+            let values = reorder(table_values, &indices);
+            (0..sizes.len())
+                .map(move |skip| NS2::read_value(&sizes[skip..].to_vec(), &values[skip..].to_vec()))
+        })
+}
+
+/// The orders the secret's digits may be spread over the tables in, most significant first: as
+/// the tables appear in the file, then as for `least_changed_first`. With a key, there's just the
+/// one order it gives.
+fn table_orders(table_sizes: &[Vec<u8>], key: Option<&[u8]>) -> Vec<Vec<usize>> {
+    match key {
+        Some(key) => vec![keyed_order(table_sizes, key)],
+        None => {
+            let original = (0..table_sizes.len()).collect();
+            vec![original, NS2::least_changed_order(&table_sizes.to_vec())]
+        }
+    }
+}
+
+/// The tables' indices, shuffled by `key`. An order an unkeyed read would also try is redrawn, for
+/// as long as there are enough tables that others are likely.
+fn keyed_order(table_sizes: &[Vec<u8>], key: &[u8]) -> Vec<usize> {
+    let unkeyed = table_orders(table_sizes, None);
+
+    let mut order = Vec::new();
+    for attempt in 0u32..16 {
+        // Fisher-Yates, with its random numbers drawn from HMACs of a counter
+        let mut random = (0u32..).flat_map(|block| {
+            let message = [attempt.to_be_bytes(), block.to_be_bytes()].concat();
+            let hash = hmac_sha256(key, &message);
+            (0..8).map(move |word| u32::from_be_bytes(hash[4 * word..][..4].try_into().unwrap()))
+        });
+
+        order = (0..table_sizes.len()).collect();
+        for index in (1..order.len()).rev() {
+            let other = random.next().unwrap() as usize % (index + 1);
+            order.swap(index, other);
+        }
+
+        if !unkeyed.contains(&order) {
+            break;
+        }
+    }
+    order
+}
+
+/// The tables holding each of `total_shards` shards, each group in file order. The largest tables
+/// are dealt out first, each to the group with the least capacity so far, to keep the groups even.
+fn ecc_groups(table_sizes: &[Vec<u8>], total_shards: usize) -> Option<Vec<Vec<usize>>> {
+    if total_shards == 0 || total_shards > table_sizes.len() {
+        return None;
     }
 
-    Ok(Some(data[2..].to_vec()))
+    let mut indices = (0..table_sizes.len()).collect::<Vec<_>>();
+    indices.sort_by_cached_key(|&index| std::cmp::Reverse(table_sizes[index].max_base_value()));
+
+    let mut groups = vec![(BigUint::from(1u8), Vec::new()); total_shards];
+    for index in indices {
+        let (capacity, group) = groups.iter_mut().min_by(|a, b| a.0.cmp(&b.0)).unwrap();
+        *capacity *= table_sizes[index].max_base_value();
+        group.push(index);
+    }
+
+    Some(
+        groups
+            .into_iter()
+            .map(|(_, mut group)| {
+                group.sort_unstable();
+                group
+            })
+            .collect(),
+    )
+}
+
+/// A secret written with `ecc`, tried for each number of shards the tables could hold
+fn ecc_secret(table_sizes: &[Vec<u8>], table_values: &[Vec<u8>]) -> Option<Vec<u8>> {
+    ecc_candidates(table_sizes, table_values)
+        .into_iter()
+        .flatten()
+        .next()
+}
+
+/// What each number of shards recovers. Every number is tried before one is picked, so the timing
+/// doesn't show which, if any, recovered.
+fn ecc_candidates(table_sizes: &[Vec<u8>], table_values: &[Vec<u8>]) -> Vec<Option<Vec<u8>>> {
+    (1..=table_sizes.len())
+        .map(|total_shards| {
+            let records = ecc_records(table_sizes, table_values, total_shards)?;
+            ecc::recover(records, total_shards)
+        })
+        .collect()
+}
+
+/// The records each of `total_shards` groups of tables hold
+fn ecc_records(
+    table_sizes: &[Vec<u8>],
+    table_values: &[Vec<u8>],
+    total_shards: usize,
+) -> Option<Vec<Vec<u8>>> {
+    let records = ecc_groups(table_sizes, total_shards)?
+        .into_iter()
+        .map(|group| {
+            let sizes = reorder(table_sizes, &group);
+            let values = reorder(table_values, &group);
+            NS2::read_value(&sizes, &values).to_bytes_be()
+        })
+        .collect();
+    Some(records)
+}
+
+fn reorder(tables: &[Vec<u8>], indices: &[usize]) -> Vec<Vec<u8>> {
+    indices.iter().map(|&index| tables[index].clone()).collect()
+}
+
+// Whether or not a message is present, this does the same work: the header is always copied into a
+// fixed-size buffer and every byte of it compared, so timing reads doesn't reveal which images
+// carry a message.
+fn has_header(data: &[u8], expected: &[u8; 2]) -> Choice {
+    let mut header = [0u8; 2];
+    let available = data.len().min(header.len());
+    header[..available].copy_from_slice(&data[..available]);
+
+    let is_whole = Choice::from((data.len() >= header.len()) as u8);
+    header.ct_eq(expected) & is_whole
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::jpeg::segments::ToVec;
+    use crate::jpeg::test_image::{decode_pixels, insert_segment, segment, TestImage};
+    use crate::split_table_values;
+
+    fn write(input: &[u8], secret: &[u8], options: &WriteOptions) -> Result<Vec<u8>> {
+        let mut output = Vec::new();
+        write_secret(&mut Cursor::new(input), &mut output, secret, options)?;
+        Ok(output)
+    }
+
+    fn read(input: &[u8]) -> Option<Vec<u8>> {
+        read_secret(&mut Cursor::new(input)).unwrap()
+    }
+
+    #[test]
+    fn test_has_header() {
+        let check = |data: &[u8]| bool::from(has_header(data, &SECRET_HEADER));
+        assert!(check(&[0xBE, 0xEF, 0x41]));
+        assert!(!check(&[0xBE, 0xEE, 0x41]));
+        assert!(!check(&[0xBF, 0xEF, 0x41]));
+        assert!(!check(&[0x41, 0xBE, 0xEF]));
+        assert!(check(&[0xBE, 0xEF]));
+        assert!(!check(&[0xBE]));
+        assert!(!check(&[]));
+    }
+
+    #[test]
+    fn test_secret_after_header() {
+        let (yes, no) = (Choice::from(1), Choice::from(0));
+        let plain = [&SECRET_HEADER[..], b"secret"].concat();
+        let filled = [
+            &FILLED_SECRET_HEADER[..],
+            &[0, 0, 0, 6],
+            b"secret",
+            &[0x55; 4],
+        ]
+        .concat();
+
+        // Only the comparisons passed in decide how the data is read
+        assert_eq!(
+            secret_after_header(&plain, yes, no),
+            Some(b"secret".to_vec())
+        );
+        assert_eq!(secret_after_header(&plain, no, no), None);
+        assert_eq!(
+            secret_after_header(&filled, no, yes),
+            Some(b"secret".to_vec())
+        );
+        assert_eq!(secret_after_header(&filled, no, no), None);
+        assert_eq!(secret_after_header(&filled[..7], no, yes), None);
+
+        assert_eq!(decode_secret(&plain), Some(b"secret".to_vec()));
+        assert_eq!(decode_secret(&filled), Some(b"secret".to_vec()));
+    }
+
+    #[test]
+    fn test_dnl_height() {
+        let input = TestImage::new(48, 32).build();
+
+        // Leave the height to a DNL segment after the scan, as some encoders writing a stream do
+        let dnl = |data: &[u8]| {
+            let mut data = data.to_vec();
+            let sof = data
+                .windows(2)
+                .position(|bytes| bytes == [0xFF, 0xC0])
+                .unwrap();
+            assert_eq!(data[sof + 5..sof + 7], [0, 32]);
+            data[sof + 5..sof + 7].copy_from_slice(&[0, 0]);
+            insert_segment(&mut data, 0xD9, 0, 0xDC, &[0, 32]);
+            data
+        };
+
+        let options = WriteOptions::default();
+        let output = write(&dnl(&input), b"secret", &options).unwrap();
+        assert_eq!(read(&output), Some(b"secret".to_vec()));
+        assert_eq!(output, dnl(&write(&input, b"secret", &options).unwrap()));
+
+        let mut no_dnl = dnl(&input);
+        let position = no_dnl.len() - 8;
+        assert_eq!(no_dnl.drain(position..position + 6).next(), Some(0xFF));
+        let error = write(&no_dnl, b"secret", &options).unwrap_err();
+        assert!(error.to_string().contains("no DNL segment"), "{error}");
+    }
+
+    #[test]
+    fn test_unchanged_tables_copy_scan() {
+        let write_zero = |input: &[u8]| {
+            let mut output = Vec::new();
+            write_secret_biguint(&mut Cursor::new(input), &mut output, &BigUint::zero())?;
+            Ok::<_, anyhow::Error>(output)
+        };
+
+        // An empty payload leaves every table of an untouched file as it is, and the file with it
+        let input = TestImage::new(48, 32).build();
+        assert_eq!(write_zero(&input).unwrap(), input);
+        let mut stripped = Vec::new();
+        assert!(!strip_secret(&mut Cursor::new(&input), &mut stripped).unwrap());
+        assert_eq!(stripped, input);
+
+        // Scan data that can't be decoded is copied through, not re-encoded
+        let mut input = TestImage::new(48, 32).build();
+        let eoi = input.len() - 2;
+        input.drain(eoi - 5..eoi);
+        assert_eq!(write_zero(&input).unwrap(), input);
+
+        // An empty secret's header still permutes the tables, so the scan is re-encoded
+        let error = write(&input, b"", &WriteOptions::default()).unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<StegError>(),
+            Some(StegError::EntropyDesync { .. })
+        ));
+
+        // As is one whose restart interval changes
+        let input = include_bytes!("../docs/dove-small-in.jpg");
+        let mut jpeg = Jpeg::read_segments(&mut Cursor::new(input)).unwrap();
+        jpeg.set_restart_interval(2);
+        let mut output = Vec::new();
+        jpeg.process_segments_mut(DhtWriter::new(&mut output, |_: &mut HuffmanTableData| {}))
+            .unwrap();
+        assert_ne!(output, input);
+        assert_eq!(decode_pixels(&output), decode_pixels(input));
+    }
+
+    #[test]
+    fn test_missing_eoi() {
+        let input = TestImage::new(48, 32).build();
+        let output = write(
+            &input[..input.len() - 2],
+            b"secret",
+            &WriteOptions::default(),
+        )
+        .unwrap();
+        assert!(output.ends_with(&[0xFF, 0xD9]));
+        assert_eq!(read(&output), Some(b"secret".to_vec()));
+        assert_eq!(decode_pixels(&output), decode_pixels(&input));
+
+        // Cut between a 0xFF in the scan and the 0x00 that made it data, leaving the scan short
+        let input = include_bytes!("../docs/dove-small-in.jpg");
+        let scan = input
+            .windows(2)
+            .position(|bytes| bytes == [0xFF, 0xDA])
+            .unwrap();
+        let cut = scan
+            + input[scan..]
+                .windows(2)
+                .position(|bytes| bytes == [0xFF, 0x00])
+                .unwrap();
+        let error = write(&input[..cut + 1], b"secret", &WriteOptions::default()).unwrap_err();
+        assert!(
+            error.to_string().starts_with("Entropy decoding lost sync"),
+            "{error}"
+        );
+    }
+
+    #[test]
+    fn test_oversubscribed_table() {
+        let input = TestImage::new(48, 32).build();
+        let jpeg = Jpeg::read_segments(&mut Cursor::new(&input)).unwrap();
+        let mut tables = Vec::new();
+        for segment in jpeg.segments.iter().filter(|s| s.marker == Marker::DHT) {
+            tables.extend(DhtData::try_from(&segment.data[..]).unwrap().tables);
+        }
+
+        // One more code of the shortest length than the AC table has room for
+        let table = &mut tables[1];
+        let length = table.sizes.iter().position(|&count| count > 0).unwrap();
+        table.sizes[length] = 1 << (length + 1);
+        let values = table
+            .sizes
+            .iter()
+            .map(|&count| count as usize)
+            .sum::<usize>();
+        table.values.resize(values, 0);
+        let dht = DhtData { tables }.to_vec();
+
+        let mut malformed = Vec::new();
+        for other in jpeg.segments.iter().filter(|s| s.marker != Marker::DHT) {
+            if other.marker == Marker::SOS {
+                segment(&mut malformed, 0xC4, &dht);
+            }
+            Jpeg::write_segment(&mut malformed, other).unwrap();
+        }
+
+        let error = write(&malformed, b"secret", &WriteOptions::default()).unwrap_err();
+        assert!(
+            error
+                .to_string()
+                .starts_with("AC table 0 is malformed: Huffman table is oversubscribed"),
+            "{error}"
+        );
+        assert!(
+            !check_fit(&mut Cursor::new(&malformed), b"secret")
+                .unwrap()
+                .fits
+        );
+    }
+
+    #[test]
+    fn test_on_table_fill() {
+        use std::sync::Mutex;
+
+        let input = TestImage::new(48, 32).build();
+        let fills = |secret: &[u8], options: WriteOptions| {
+            let fills = Arc::new(Mutex::new(Vec::new()));
+            let on_table_fill: TableFillObserver = {
+                let fills = fills.clone();
+                Arc::new(move |class, index, used, available| {
+                    fills.lock().unwrap().push((class, index, used, available))
+                })
+            };
+            let options = WriteOptions {
+                on_table_fill: Some(on_table_fill),
+                ..options
+            };
+            write(&input, secret, &options).unwrap();
+            let fills = fills.lock().unwrap().clone();
+            fills
+        };
+
+        let short = fills(b"hi", WriteOptions::default());
+        let keys = short
+            .iter()
+            .map(|&(class, index, ..)| (class, index))
+            .collect::<Vec<_>>();
+        assert_eq!(keys, [(0, 0), (1, 0), (0, 1), (1, 1)]);
+        assert!(short.iter().all(|&(.., used, available)| used <= available));
+
+        // The last table takes the least significant bits, and a short secret fits in it alone
+        assert!(short[..3].iter().all(|&(.., used, _)| used == 0));
+        assert!(short[3].2 > 0);
+
+        // Filled to capacity, every table is close to full
+        let full = fills(
+            b"hi",
+            WriteOptions {
+                fill_remaining: Some(7),
+                ..Default::default()
+            },
+        );
+        assert!(full
+            .iter()
+            .all(|&(.., used, available)| used + 8 >= available));
+    }
+
+    #[test]
+    fn test_ecc_read_timing() {
+        let input = TestImage::new(48, 32).build();
+        let ecc = WriteOptions {
+            ecc: Some(EccParams {
+                data_shards: 1,
+                parity_shards: 1,
+            }),
+            ..Default::default()
+        };
+        let carriers = [
+            input.clone(),
+            write(&input, b"secret", &WriteOptions::default()).unwrap(),
+            write(&input, b"secret", &ecc).unwrap(),
+        ];
+
+        // Every shard count is tried, whether or not one of them recovers a secret
+        let candidates = carriers
+            .iter()
+            .map(|carrier| {
+                let jpeg = Jpeg::read_segments(&mut Cursor::new(carrier)).unwrap();
+                let (table_sizes, table_values) = scan_table_data(&read_tables(&jpeg).unwrap());
+                ecc_candidates(&table_sizes, &table_values)
+            })
+            .collect::<Vec<_>>();
+        assert!(candidates.iter().all(|candidates| candidates.len() == 4));
+        assert_eq!(candidates[0].iter().flatten().count(), 0);
+        assert_eq!(candidates[2].iter().flatten().count(), 1);
+        assert_eq!(read(&carriers[2]), Some(b"secret".to_vec()));
+    }
+
+    #[test]
+    fn test_empty_secret() {
+        let input = TestImage::new(48, 32).build();
+
+        // The header alone is an empty secret, distinct from the zero value of untouched tables
+        let output = write(&input, b"", &WriteOptions::default()).unwrap();
+        assert_eq!(read(&output), Some(Vec::new()));
+        assert_eq!(peek_secret_len(&mut Cursor::new(&output)).unwrap(), Some(0));
+        let value = read_secret_biguint(&mut Cursor::new(&output)).unwrap();
+        assert_eq!(value, Some(BigUint::from_bytes_be(&SECRET_HEADER)));
+
+        assert_eq!(read(&input), None);
+        assert_eq!(peek_secret_len(&mut Cursor::new(&input)).unwrap(), None);
+        let value = read_secret_biguint(&mut Cursor::new(&input)).unwrap();
+        assert_eq!(value, Some(BigUint::zero()));
+
+        // Writing zero leaves every table sorted, as stripping the secret does
+        let mut zero = Vec::new();
+        write_secret_biguint(&mut Cursor::new(&output), &mut zero, &BigUint::zero()).unwrap();
+        let mut stripped = Vec::new();
+        strip_secret(&mut Cursor::new(&output), &mut stripped).unwrap();
+        assert_eq!(zero, stripped);
+    }
+
+    #[test]
+    fn test_leading_zeros() {
+        let input = TestImage::new(48, 32).build();
+        for secret in [&[0x00, 0x00, 0x41][..], &[0x00], &[0x00; 8]] {
+            let output = write(&input, secret, &WriteOptions::default()).unwrap();
+            assert_eq!(read(&output).unwrap(), secret);
+        }
+    }
+
+    #[test]
+    fn test_fill_remaining() {
+        let input = TestImage::new(48, 32).build();
+        let options = |seed| WriteOptions {
+            fill_remaining: Some(seed),
+            ..Default::default()
+        };
+
+        let output = write(&input, b"secret", &options(7)).unwrap();
+        assert_eq!(read(&output).unwrap(), b"secret");
+        assert_eq!(output, write(&input, b"secret", &options(7)).unwrap());
+        assert_ne!(output, write(&input, b"secret", &options(8)).unwrap());
+        assert_eq!(
+            read(&write(&input, b"", &options(7)).unwrap()).unwrap(),
+            b""
+        );
+
+        let write_data = write_secret(
+            &mut Cursor::new(&input),
+            &mut Vec::new(),
+            b"secret",
+            &options(7),
+        )
+        .unwrap();
+        assert!(write_data.fill_ratio > 1e-3);
+        assert!(write_data.secret_size >= capacity_of(&input) - 1);
+
+        let max_size = capacity(&mut Cursor::new(&input), &options(7)).unwrap();
+        assert!(max_size >= capacity_of(&input) - FILLED_SECRET_HEADER.len() - 5);
+        assert!(write(&input, &vec![0x55; max_size - 1], &options(7)).is_ok());
+        let error = write(&input, &vec![0x55; max_size + 1], &options(7)).unwrap_err();
+        assert_eq!(
+            error.downcast_ref(),
+            Some(&StegError::PayloadTooLarge {
+                attempted: max_size + 1,
+                max: max_size
+            })
+        );
+    }
+
+    fn capacity_of(input: &[u8]) -> usize {
+        let jpeg = Jpeg::read_headers_only(&mut Cursor::new(input)).unwrap();
+        let (table_sizes, _) = scan_table_data(&read_tables(&jpeg).unwrap());
+        table_sizes.max_base_value().to_bytes_be().len()
+    }
+
+    #[test]
+    fn test_capacity() {
+        let input = TestImage::new(48, 32).build();
+        let mut output = Vec::new();
+        let write_data = write_secret(
+            &mut Cursor::new(&input),
+            &mut output,
+            b"secret",
+            &WriteOptions::default(),
+        )
+        .unwrap();
+
+        assert_eq!(capacity_of(&input), write_data.approx_max_size);
+        assert_eq!(capacity_of(&output), write_data.approx_max_size);
+
+        let options = [
+            WriteOptions::default(),
+            WriteOptions {
+                fill_remaining: Some(7),
+                ..Default::default()
+            },
+            WriteOptions {
+                ecc: Some(EccParams {
+                    data_shards: 1,
+                    parity_shards: 1,
+                }),
+                ..Default::default()
+            },
+        ];
+        for options in options {
+            let max = capacity(&mut Cursor::new(&input), &options).unwrap();
+            assert!(max > 0);
+
+            // Whatever its contents
+            for byte in [0x00, 0xFF] {
+                assert!(write(&input, &vec![byte; max], &options).is_ok());
+            }
+            let error = write(&input, &vec![0xFF; max + 1], &options).unwrap_err();
+            assert_eq!(
+                error.downcast_ref(),
+                Some(&StegError::PayloadTooLarge {
+                    attempted: max + 1,
+                    max
+                })
+            );
+        }
+    }
+
+    #[test]
+    fn test_check_fit() {
+        let input = TestImage::new(48, 32).build();
+        let max_size = capacity_of(&input);
+
+        let report = check_fit(&mut Cursor::new(&input), b"secret").unwrap();
+        assert!(report.fits);
+        assert_eq!(report.approx_max_size, max_size);
+        assert_eq!(report.secret_size, 8);
+
+        let secret = vec![0xFF; max_size];
+        let report = check_fit(&mut Cursor::new(&input), &secret).unwrap();
+        assert!(!report.fits);
+        assert_eq!(report.secret_size, max_size + 2);
+
+        let error = write(&input, &secret, &WriteOptions::default()).unwrap_err();
+        let max = capacity(&mut Cursor::new(&input), &WriteOptions::default()).unwrap();
+        assert!(max >= max_size - SECRET_HEADER.len() - 1);
+        assert_eq!(
+            error.downcast_ref(),
+            Some(&StegError::PayloadTooLarge {
+                attempted: max_size,
+                max
+            })
+        );
+        assert!(write(&input, &vec![0x55; max - 1], &WriteOptions::default()).is_ok());
+    }
+
+    #[test]
+    fn test_check_fit_refused_carrier() {
+        let input = TestImage::new(48, 32).progressive().build();
+        let report = check_fit(&mut Cursor::new(&input), b"secret").unwrap();
+        assert!(!report.fits);
+        assert_eq!(report.secret_size, 8);
+
+        let error = write(&input, b"secret", &WriteOptions::default()).unwrap_err();
+        assert_eq!(error.downcast_ref(), Some(&StegError::Progressive));
+    }
+
+    #[test]
+    fn test_trailer() {
+        let trailer = [
+            0x00, 0xFF, 0xD8, 0x13, 0x37, 0xFF, 0xD9, 0xFF, 0x00, 0x42, 0xFF, 0xC4, 0x00, 0x02,
+            0x01, 0xFF,
+        ];
+        let mut input = TestImage::new(48, 32).build();
+        input.extend(trailer);
+
+        let output = write(&input, b"secret", &WriteOptions::default()).unwrap();
+        assert!(output.ends_with(&trailer));
+        assert_eq!(read(&output).unwrap(), b"secret");
+    }
+
+    #[test]
+    fn test_ratio() {
+        let ratio_of = |n: u64, d: u64| ratio(&BigUint::from(n), &BigUint::from(d));
+        assert_eq!(ratio_of(0, 10), 0.0);
+        assert_eq!(ratio_of(1, 4), 0.25);
+        assert_eq!(ratio_of(5, 0), 0.0);
+
+        // Well past f64's range, where only the leading bits matter
+        let denominator = BigUint::from(3u32) << 2000u32;
+        let numerator = BigUint::from(2u32) << 2000u32;
+        assert!((ratio(&numerator, &denominator) - 2.0 / 3.0).abs() < 1e-12);
+        let tiny = ratio(&(BigUint::from(1u32) << 1500u32), &denominator);
+        assert!((tiny / 2f64.powi(-500) - 1.0 / 3.0).abs() < 1e-12);
+
+        // A secret a byte or so short of the capacity
+        let input = TestImage::new(48, 32).build();
+        let secret = vec![0x55; capacity_of(&input) - 3];
+        let write_data = write_secret(
+            &mut Cursor::new(&input),
+            &mut Vec::new(),
+            &secret,
+            &WriteOptions::default(),
+        )
+        .unwrap();
+        assert!(write_data.fill_ratio > 1e-5 && write_data.fill_ratio < 1.0);
+    }
+
+    #[test]
+    fn test_unknown_scan_component() {
+        let mut input = TestImage::new(48, 32).build();
+        let sos = input
+            .windows(2)
+            .position(|bytes| bytes == [0xFF, 0xDA])
+            .unwrap();
+        input[sos + 5] = 9;
+
+        let error = write(&input, b"secret", &WriteOptions::default()).unwrap_err();
+        assert!(error.to_string().contains("component 9"));
+    }
+
+    #[test]
+    fn test_prepared_carrier() {
+        let input = TestImage::new(48, 32).build();
+        let carrier =
+            PreparedCarrier::new(&mut Cursor::new(&input), &WriteOptions::default()).unwrap();
+
+        for secret in [&b"first"[..], b"second", b"a third secret"] {
+            let output = carrier.embed(secret).unwrap();
+            assert_eq!(
+                output,
+                write(&input, secret, &WriteOptions::default()).unwrap()
+            );
+            assert_eq!(read(&output).unwrap(), secret);
+        }
+    }
+
+    #[test]
+    fn test_coding_mode() {
+        let mode = |input: &[u8]| coding_mode(&mut Cursor::new(input)).unwrap();
+
+        let input = TestImage::new(48, 32).build();
+        assert_eq!(mode(&input), CodingMode::Baseline);
+        assert_eq!(
+            mode(&TestImage::new(48, 32).twelve_bit().build()),
+            CodingMode::ExtendedSequential
+        );
+        assert_eq!(
+            mode(&TestImage::new(48, 32).progressive().build()),
+            CodingMode::Progressive
+        );
+
+        let sof = input
+            .windows(2)
+            .position(|bytes| bytes == [0xFF, 0xC0])
+            .unwrap();
+        for (marker, expected) in [
+            (0xC9, CodingMode::Arithmetic),
+            (0xCE, CodingMode::Arithmetic),
+            (0xC3, CodingMode::Unsupported(0xC3)),
+        ] {
+            let mut input = input.clone();
+            input[sof + 1] = marker;
+            assert_eq!(mode(&input), expected);
+        }
+    }
+
+    #[test]
+    fn test_table_report() {
+        let input = TestImage::new(48, 32).build();
+        let reports = table_report(&mut Cursor::new(&input)).unwrap();
+        assert_eq!(reports.len(), 4);
+
+        // DC luminance: 1, 5, 1, 1, ... codes per length leaves just the five 3-bit codes (5! = 120)
+        let dc = &reports[0];
+        assert_eq!((dc.table_class, dc.table_index), (0, 0));
+        assert_eq!(dc.usable_values, 5);
+        assert_eq!(dc.bits_of_capacity, 6);
+
+        let total_bits = reports.iter().map(|r| r.bits_of_capacity).sum::<u64>();
+        assert!(total_bits <= 8 * capacity_of(&input) as u64);
+        assert!(total_bits + 8 * reports.len() as u64 >= 8 * (capacity_of(&input) - 1) as u64);
+    }
+
+    #[test]
+    fn test_unread_scan_data() {
+        let options = WriteOptions::default();
+
+        let mut input = TestImage::new(48, 32).build();
+        let eoi = input.len() - 2;
+        input.splice(eoi..eoi, [0x12, 0x34]);
+        let error = write(&input, b"secret", &options).unwrap_err();
+        match error.downcast_ref::<StegError>() {
+            Some(&StegError::EntropyDesync {
+                read_bits,
+                total_bits,
+            }) => assert!(total_bits - read_bits > 16),
+            _ => panic!("Expected an entropy desync, got: {error}"),
+        }
+
+        // Cutting the scan short runs the decoder out of data partway through a block
+        let mut input = TestImage::new(48, 32).build();
+        let eoi = input.len() - 2;
+        input.drain(eoi - 5..eoi);
+        let error = write(&input, b"secret", &options).unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<StegError>(),
+            Some(StegError::EntropyDesync { .. })
+        ));
+
+        // A restart interval longer than the image, while the scan still has RST markers
+        let mut input = TestImage::new(64, 16).restart_interval(3).build();
+        let dri = input
+            .windows(2)
+            .position(|bytes| bytes == [0xFF, 0xDD])
+            .unwrap();
+        input[dri + 5] = 100;
+        assert!(write(&input, b"secret", &options).is_err());
+
+        // RST markers in the scan with no DRI segment at all
+        let mut input = TestImage::new(64, 16).restart_interval(3).build();
+        input.drain(dri..dri + 6);
+        let error = write(&input, b"secret", &options).unwrap_err();
+        assert!(error
+            .to_string()
+            .contains("no restart interval was defined"));
+
+        // A DRI segment, but no RST markers
+        let mut input = TestImage::new(64, 16).build();
+        insert_segment(&mut input, 0xDA, 0, 0xDD, &[0, 3]);
+        let error = write(&input, b"secret", &options).unwrap_err();
+        assert!(error
+            .to_string()
+            .contains("0 restart markers, but 5 are needed"));
+    }
+
+    #[test]
+    fn test_read_headers_only() {
+        let input = TestImage::new(48, 32).build();
+        let output = write(&input, b"secret", &WriteOptions::default()).unwrap();
+
+        // The secret can be found without any of the image data
+        let sos = output
+            .windows(2)
+            .position(|bytes| bytes == [0xFF, 0xDA])
+            .unwrap();
+        let headers = &output[..sos + 2 + 12];
+        assert_eq!(read(headers).unwrap(), b"secret");
+        assert!(read_secret(&mut Cursor::new(&output[..sos])).is_err());
+
+        let jpeg = Jpeg::read_headers_only(&mut Cursor::new(headers)).unwrap();
+        let markers = jpeg.segments.iter().map(|s| s.marker).collect::<Vec<_>>();
+        assert_eq!(markers.last(), Some(&Marker::SOS));
+        assert_eq!(jpeg.segments.last().unwrap().data.len(), 10);
+
+        // Tables are defined between scans, so the rest of the file is needed
+        let input = TestImage::new(48, 32).progressive().build();
+        let jpeg = Jpeg::read_headers_only(&mut Cursor::new(&input)).unwrap();
+        let scans = jpeg.segments.iter().filter(|s| s.marker == Marker::SOS);
+        assert_eq!(scans.count(), 4);
+    }
+
+    #[test]
+    fn test_ecc() {
+        let options = |data_shards, parity_shards| WriteOptions {
+            ecc: Some(EccParams {
+                data_shards,
+                parity_shards,
+            }),
+            ..Default::default()
+        };
+
+        // Each table's values sorted within their code lengths, as a re-encoder might leave them
+        let sort_tables = |input: &[u8], indices: &[usize]| {
+            let mut output = Vec::new();
+            let mut jpeg = Jpeg::read_segments(&mut Cursor::new(input)).unwrap();
+            jpeg.process_segments_mut(DhtWriter::new_batch(
+                &mut output,
+                |tables: &[HuffmanTableData]| {
+                    let sorted = |(index, table): (usize, &HuffmanTableData)| {
+                        let mut values = table.values.clone();
+                        if indices.contains(&index) {
+                            let mut start = 0;
+                            for &count in &table.sizes {
+                                values[start..start + count as usize].sort_unstable();
+                                start += count as usize;
+                            }
+                        }
+                        values
+                    };
+                    tables.iter().enumerate().map(sorted).collect()
+                },
+            ))
+            .unwrap();
+            output
+        };
+
+        // The AC luminance table on its own, then the other three
+        let input = TestImage::new(48, 32).build();
+        let (table_sizes, _) = scan_table_data(
+            &read_tables(&Jpeg::read_segments(&mut Cursor::new(&input)).unwrap()).unwrap(),
+        );
+        assert_eq!(
+            ecc_groups(&table_sizes, 2),
+            Some(vec![vec![1], vec![0, 2, 3]])
+        );
+
+        let mut output = Vec::new();
+        let write_data = write_secret(
+            &mut Cursor::new(&input),
+            &mut output,
+            b"secret",
+            &options(1, 1),
+        )
+        .unwrap();
+        assert_eq!(write_data.lost_tables_tolerated, Some(1));
+        assert_eq!(read(&output), Some(b"secret".to_vec()));
+        assert_eq!(peek_secret_len(&mut Cursor::new(&output)).unwrap(), Some(6));
+        assert_eq!(decode_pixels(&output), decode_pixels(&input));
+
+        // Either group can be lost, but not both
+        assert_eq!(read(&sort_tables(&output, &[1])), Some(b"secret".to_vec()));
+        assert_eq!(read(&sort_tables(&output, &[3])), Some(b"secret".to_vec()));
+        assert_eq!(read(&sort_tables(&output, &[1, 3])), None);
+
+        // Without shards, losing the one table a short secret is held in loses the secret
+        let output = write(&input, b"secret", &WriteOptions::default()).unwrap();
+        assert_eq!(read(&sort_tables(&output, &[3])), None);
+
+        let error = write(&input, b"secret", &options(4, 1)).unwrap_err();
+        assert!(error.to_string().contains("5 shards over 4 tables"));
+        let error = write(&input, b"secret", &options(2, 1)).unwrap_err();
+        assert!(matches!(
+            error.downcast_ref(),
+            Some(StegError::PayloadTooLarge { attempted: 6, .. })
+        ));
+    }
+
+    #[test]
+    fn test_read_detailed() {
+        let detailed = |input: &[u8]| read_secret_detailed(&mut Cursor::new(input)).unwrap();
+        let options = WriteOptions {
+            ecc: Some(EccParams {
+                data_shards: 1,
+                parity_shards: 1,
+            }),
+            ..Default::default()
+        };
+
+        // Swapping the last two values that share a code length only changes the lowest digit of
+        // the table's value, so just the end of the record it holds, its checksum, is damaged
+        let swap_last = |input: &[u8], indices: &[usize]| {
+            let mut output = Vec::new();
+            let mut jpeg = Jpeg::read_segments(&mut Cursor::new(input)).unwrap();
+            jpeg.process_segments_mut(DhtWriter::new_batch(
+                &mut output,
+                |tables: &[HuffmanTableData]| {
+                    let swapped = |(index, table): (usize, &HuffmanTableData)| {
+                        let mut values = table.values.clone();
+                        if indices.contains(&index) {
+                            let (mut start, mut end) = (0, 0);
+                            for &count in &table.sizes {
+                                start += count as usize;
+                                if count > 1 {
+                                    end = start;
+                                }
+                            }
+                            values.swap(end - 2, end - 1);
+                        }
+                        values
+                    };
+                    tables.iter().enumerate().map(swapped).collect()
+                },
+            ))
+            .unwrap();
+            output
+        };
+
+        let input = TestImage::new(48, 32).build();
+        assert_eq!(detailed(&input), None);
+
+        let plain = write(&input, b"secret", &WriteOptions::default()).unwrap();
+        let expected = DetailedSecret {
+            secret: b"secret".to_vec(),
+            checksum_ok: true,
+        };
+        assert_eq!(detailed(&plain), Some(expected.clone()));
+
+        // One group damaged is recovered from the other, but not both
+        let output = write(&input, b"secret", &options).unwrap();
+        assert_eq!(detailed(&swap_last(&output, &[3])), Some(expected.clone()));
+
+        let damaged = swap_last(&output, &[1, 3]);
+        assert_eq!(read(&damaged), None);
+        assert_eq!(
+            detailed(&damaged),
+            Some(DetailedSecret {
+                checksum_ok: false,
+                ..expected
+            })
+        );
+    }
+
+    #[test]
+    fn test_pad_to_len() {
+        let options = |pad_to_len| WriteOptions {
+            pad_to_len: Some(pad_to_len),
+            ..Default::default()
+        };
+
+        let input = TestImage::new(48, 32).build();
+        let unpadded = write(&input, b"secret", &WriteOptions::default()).unwrap();
+
+        for target_len in [unpadded.len(), unpadded.len() + 4, input.len() + 100] {
+            let output = write(&input, b"secret", &options(target_len)).unwrap();
+            assert_eq!(output.len(), target_len);
+            assert!(output.ends_with(&[0xFF, 0xD9]));
+            assert_eq!(read(&output), Some(b"secret".to_vec()));
+            assert_eq!(decode_pixels(&output), decode_pixels(&input));
+        }
+
+        // A comment can't be shorter than 4 bytes, nor can the output be cut down
+        for target_len in [unpadded.len() + 3, unpadded.len() - 1] {
+            let error = write(&input, b"secret", &options(target_len)).unwrap_err();
+            assert!(error.to_string().contains("Can't pad"));
+        }
+    }
+
+    #[test]
+    fn test_integrity() {
+        let input = TestImage::new(48, 32).build();
+        let sign = |input: &[u8], key: &[u8]| {
+            let mut output = Vec::new();
+            sign_tables(&mut Cursor::new(input), &mut output, key).unwrap();
+            output
+        };
+        let verify =
+            |input: &[u8], key: &[u8]| verify_integrity(&mut Cursor::new(input), key).unwrap();
+
+        let output = sign(&input, b"key");
+        assert!(verify(&output, b"key"));
+        assert!(!verify(&output, b"other key"));
+        assert!(!verify(&input, b"key"));
+        assert_eq!(read(&output), None);
+        assert_eq!(decode_pixels(&output), decode_pixels(&input));
+
+        // Anything else embedded since breaks the seal
+        let rewritten = write(&output, b"secret", &WriteOptions::default()).unwrap();
+        assert!(!verify(&rewritten, b"key"));
+
+        // Signing the same tables in any order gives the same tag
+        assert!(verify(&sign(&rewritten, b"key"), b"key"));
+        assert_eq!(sign(&rewritten, b"key"), sign(&input, b"key"));
+    }
+
+    #[test]
+    fn test_batch_writer() {
+        let input = TestImage::new(48, 32).build();
+        let write_batch = |callback: &dyn Fn(&[HuffmanTableData]) -> Vec<Vec<u8>>| {
+            let mut output = Vec::new();
+            let mut jpeg = Jpeg::read_segments(&mut Cursor::new(&input)).unwrap();
+            jpeg.process_segments_mut(DhtWriter::new_batch(&mut output, callback))
+                .map(|_| output)
+        };
+
+        let unchanged = |tables: &[HuffmanTableData]| {
+            assert_eq!(tables.len(), 4);
+            tables.iter().map(|table| table.values.clone()).collect()
+        };
+        let output = write_batch(&unchanged).unwrap();
+        assert_eq!(
+            table_permutations(&mut Cursor::new(&output)).unwrap(),
+            table_permutations(&mut Cursor::new(&input)).unwrap()
+        );
+
+        let error = write_batch(&|_| Vec::new()).unwrap_err();
+        assert!(error.to_string().contains("values for 4 tables, but got 0"));
+    }
+
+    #[test]
+    fn test_resave() {
+        let input = TestImage::new(37, 19)
+            .components(&[(1, 2, 2), (2, 1, 1), (3, 1, 1)])
+            .restart_interval(2)
+            .build();
+
+        let mut output = Vec::new();
+        resave(&mut Cursor::new(&input), &mut output).unwrap();
+        assert_eq!(decode_pixels(&output), decode_pixels(&input));
+        assert_eq!(read(&output), None);
+        assert_eq!(
+            table_permutations(&mut Cursor::new(&output)).unwrap(),
+            table_permutations(&mut Cursor::new(&input)).unwrap()
+        );
+
+        let embedded = write(&input, b"secret", &WriteOptions::default()).unwrap();
+        assert_ne!(embedded, output);
+        assert_eq!(decode_pixels(&embedded), decode_pixels(&output));
+    }
+
+    #[test]
+    fn test_empty_table() {
+        let mut input = TestImage::new(48, 32).build();
+        let jpeg = Jpeg::read_segments(&mut Cursor::new(&input)).unwrap();
+        let (table, _) = read_tables(&jpeg).unwrap().remove(0);
+
+        // Both are redefined before the scan, by the file's own tables
+        let dht = DhtData {
+            tables: vec![
+                table,
+                HuffmanTableData {
+                    table_class: 1,
+                    table_index: 1,
+                    sizes: vec![0; 16],
+                    values: Vec::new(),
+                },
+            ],
+        };
+        insert_segment(&mut input, 0xC4, 0, 0xC4, &dht.to_vec());
+
+        let output = write(&input, b"secret", &WriteOptions::default()).unwrap();
+        assert_eq!(read(&output), Some(b"secret".to_vec()));
+
+        let jpeg = Jpeg::read_segments(&mut Cursor::new(&output)).unwrap();
+        let tables = read_tables(&jpeg).unwrap();
+        assert_eq!(tables[1].0.sizes, [0; 16]);
+        assert!(tables[1].0.values.is_empty());
+    }
+
+    #[test]
+    fn test_write_progressive() {
+        let input = TestImage::new(48, 32).progressive().build();
+        let error = write(&input, b"secret", &WriteOptions::default()).unwrap_err();
+        assert_eq!(error.downcast_ref(), Some(&StegError::Progressive));
+
+        let error = PreparedCarrier::new(&mut Cursor::new(&input), &Default::default());
+        assert_eq!(
+            error.err().unwrap().downcast_ref(),
+            Some(&StegError::Progressive)
+        );
+    }
+
+    #[test]
+    fn test_sequential_successive_approximation() {
+        let mut input = TestImage::new(48, 32).build();
+        let sos = input
+            .windows(2)
+            .position(|bytes| bytes == [0xFF, 0xDA])
+            .unwrap();
+        input[sos + 13] = 0x10;
+
+        let error = write(&input, b"secret", &WriteOptions::default()).unwrap_err();
+        assert!(error.to_string().contains("successive approximation"));
+    }
+
+    #[test]
+    fn test_write_secret_into() {
+        let input = TestImage::new(48, 32).build();
+        let jpeg = Jpeg::read_segments(&mut Cursor::new(&input)).unwrap();
+
+        let mut output = Vec::new();
+        write_secret_into(jpeg, &mut output, b"secret").unwrap();
+        assert_eq!(
+            output,
+            write(&input, b"secret", &WriteOptions::default()).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_subsampled_partial_mcus() {
+        // 4:2:0, with a partial MCU both across and down
+        for (width, height) in [(37, 19), (17, 33), (9, 9)] {
+            let input = TestImage::new(width, height)
+                .components(&[(1, 2, 2), (2, 1, 1), (3, 1, 1)])
+                .build();
+
+            let output = write(&input, b"secret", &WriteOptions::default()).unwrap();
+            assert_eq!(read(&output).unwrap(), b"secret");
+            assert_eq!(decode_pixels(&output), decode_pixels(&input));
+        }
+    }
+
+    #[test]
+    fn test_peek_secret_len() {
+        let input = TestImage::new(48, 32).build();
+        let peek = |data: &[u8]| peek_secret_len(&mut Cursor::new(data)).unwrap();
+        let filled = WriteOptions {
+            fill_remaining: Some(1),
+            ..Default::default()
+        };
+
+        assert_eq!(peek(&input), None);
+        for secret in [&b"secret"[..], &[0x00, 0x41], &[0x41]] {
+            let output = write(&input, secret, &WriteOptions::default()).unwrap();
+            assert_eq!(peek(&output), Some(secret.len()));
+
+            let output = write(&input, secret, &filled).unwrap();
+            assert_eq!(peek(&output), Some(secret.len()));
+        }
+    }
+
+    #[test]
+    fn test_capacity_progressive() {
+        let input = TestImage::new(48, 32).progressive().build();
+
+        // A definition of AC table 0 that's replaced before any scan uses it
+        let unused_dht = DhtData {
+            tables: vec![HuffmanTableData {
+                table_class: 1,
+                table_index: 0,
+                sizes: vec![0, 0, 10, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0],
+                values: (0..10).collect(),
+            }],
+        };
+        let mut with_unused = input.clone();
+        insert_segment(&mut with_unused, 0xC4, 1, 0xC4, &unused_dht.to_vec());
+
+        // Every table of the original file is used by some scan
+        let jpeg = Jpeg::read_segments(&mut Cursor::new(&input)).unwrap();
+        assert!(read_tables(&jpeg)
+            .unwrap()
+            .iter()
+            .all(|(_, in_scan)| *in_scan));
+
+        let jpeg = Jpeg::read_segments(&mut Cursor::new(&with_unused)).unwrap();
+        let in_scan = read_tables(&jpeg)
+            .unwrap()
+            .into_iter()
+            .map(|(_, in_scan)| in_scan)
+            .collect::<Vec<_>>();
+        assert_eq!(in_scan, [true, true, true, true, false, true, true, true]);
+        assert_eq!(capacity_of(&with_unused), capacity_of(&input));
+    }
+
+    #[test]
+    fn test_read_progressive() {
+        // Another tool's embedding: the same permutation of the values, made without re-encoding
+        // any scan, as a progressive file's can't be here
+        let input = TestImage::new(48, 32).progressive().build();
+        let mut jpeg = Jpeg::read_segments(&mut Cursor::new(&input)).unwrap();
+        let (table_sizes, mut table_values) = scan_table_data(&read_tables(&jpeg).unwrap());
+        let ns = NS2::try_from_input(secret_value(b"hi"), &table_sizes).unwrap();
+        ns.permute_values(&mut table_values);
+
+        let mut table_values = table_values.into_iter();
+        for segment in &mut jpeg.segments {
+            if segment.marker == Marker::DHT {
+                let mut dht = DhtData::try_from(&segment.data[..]).unwrap();
+                for table in &mut dht.tables {
+                    table.values = table_values.next().unwrap();
+                }
+                segment.data = dht.to_vec();
+            }
+        }
+        let mut embedded = Vec::new();
+        jpeg.write_to(&mut embedded).unwrap();
+
+        assert_eq!(read(&embedded), Some(b"hi".to_vec()));
+        assert_eq!(capacity_of(&embedded), capacity_of(&input));
+        assert_eq!(
+            table_report(&mut Cursor::new(&embedded)).unwrap().len(),
+            table_sizes.len()
+        );
+
+        // None of it looks at the image data, which can be anything at all
+        let sos = embedded
+            .windows(2)
+            .rposition(|bytes| bytes == [0xFF, 0xDA])
+            .unwrap();
+        let eoi = embedded.len() - 2;
+        let header_len = u16::from_be_bytes([embedded[sos + 2], embedded[sos + 3]]) as usize;
+        embedded[sos + 2 + header_len..eoi].fill(0x5A);
+        assert_eq!(read(&embedded), Some(b"hi".to_vec()));
+        assert_eq!(capacity_of(&embedded), capacity_of(&input));
+    }
+
+    #[test]
+    fn test_set_restart_interval() {
+        let embed = |input: &[u8], count| {
+            let mut jpeg = Jpeg::read_segments(&mut Cursor::new(input)).unwrap();
+            jpeg.set_restart_interval(count);
+            let mut output = Vec::new();
+            write_secret_into(jpeg, &mut output, b"secret").unwrap();
+            output
+        };
+        let count_markers = |data: &[u8], marker: u8| {
+            data.windows(2)
+                .filter(|bytes| bytes[0] == 0xFF && bytes[1] == marker)
+                .count()
+        };
+
+        // Test images' DC coefficients wander too far for their differences to be re-encoded from
+        // zero, so this takes a real photo
+        let input = include_bytes!("../docs/dove-small-in.jpg").to_vec();
+        assert_eq!(count_markers(&input, 0xDD), 0);
+        let output = embed(&input, 3);
+        assert_eq!(count_markers(&output, 0xDD), 1);
+        assert!((0xD0..=0xD7).all(|marker| count_markers(&output, marker) > 0));
+        assert_eq!(read(&output), Some(b"secret".to_vec()));
+        assert_eq!(decode_pixels(&output), decode_pixels(&input));
+
+        // Changing and removing existing ones
+        let with_restarts = output;
+        for count in [2, 0] {
+            let output = embed(&with_restarts, count);
+            let dri = output
+                .windows(2)
+                .position(|bytes| bytes == [0xFF, 0xDD])
+                .unwrap();
+            assert_eq!(output[dri + 4..dri + 6], [0, count as u8]);
+            assert_eq!(read(&output), Some(b"secret".to_vec()));
+            assert_eq!(decode_pixels(&output), decode_pixels(&input));
+        }
+        let output = embed(&with_restarts, 0);
+        assert!((0xD0..=0xD7).all(|marker| count_markers(&output, marker) == 0));
+
+        // A difference too large for any DC category is an error, not a corrupt scan
+        let input = TestImage::new(64, 16).restart_interval(3).build();
+        let mut jpeg = Jpeg::read_segments(&mut Cursor::new(&input)).unwrap();
+        jpeg.set_restart_interval(0);
+        assert!(write_secret_into(jpeg, &mut Vec::new(), b"secret").is_err());
+
+        let mut jpeg = Jpeg::read_segments(&mut Cursor::new(&input)).unwrap();
+        jpeg.set_restart_interval(70000);
+        assert!(write_secret_into(jpeg, &mut Vec::new(), b"secret").is_err());
+    }
+
+    #[test]
+    fn test_apply_permutation() {
+        let input = TestImage::new(48, 32).build();
+        let tables = read_huffman_tables(&mut Cursor::new(&input)).unwrap();
+        let sizes = tables
+            .iter()
+            .flat_map(|table| table.sizes.iter().map(|&count| count as usize))
+            .collect::<Vec<_>>();
+        let values = tables
+            .iter()
+            .flat_map(|table| table.values.clone())
+            .collect::<Vec<_>>();
+
+        let encoded = secret_value(b"golden");
+        let mut output = Vec::new();
+        write_secret_biguint(&mut Cursor::new(&input), &mut output, &encoded).unwrap();
+        let written = read_huffman_tables(&mut Cursor::new(&output)).unwrap();
+        assert_eq!(
+            crate::apply_permutation(&sizes, &values, &encoded).unwrap(),
+            written
+                .into_iter()
+                .map(|table| table.values)
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_read_huffman_tables() {
+        let input = TestImage::new(48, 32).build();
+        let tables = read_huffman_tables(&mut Cursor::new(&input)).unwrap();
+        let keys = tables
+            .iter()
+            .map(|table| (table.table_class, table.table_index))
+            .collect::<Vec<_>>();
+        assert_eq!(keys, [(0, 0), (1, 0), (0, 1), (1, 1)]);
+
+        let embedded = write(&input, b"secret", &WriteOptions::default()).unwrap();
+        let embedded_tables = read_huffman_tables(&mut Cursor::new(&embedded)).unwrap();
+        for (table, embedded_table) in tables.iter().zip(&embedded_tables) {
+            assert_eq!(table.sizes, embedded_table.sizes);
+            let mut values = embedded_table.values.clone();
+            values.sort_unstable();
+            let mut original_values = table.values.clone();
+            original_values.sort_unstable();
+            assert_eq!(values, original_values);
+        }
+        assert!(tables
+            .iter()
+            .zip(&embedded_tables)
+            .any(|(table, embedded_table)| table.values != embedded_table.values));
+
+        // A table a scan never uses is still listed
+        let input = TestImage::new(48, 32).progressive().build();
+        let unused = HuffmanTableData {
+            table_class: 1,
+            table_index: 0,
+            sizes: vec![0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0],
+            values: vec![0],
+        };
+        let mut with_unused = input.clone();
+        insert_segment(
+            &mut with_unused,
+            0xC4,
+            1,
+            0xC4,
+            &DhtData {
+                tables: vec![unused],
+            }
+            .to_vec(),
+        );
+        assert_eq!(
+            read_huffman_tables(&mut Cursor::new(&with_unused))
+                .unwrap()
+                .len(),
+            read_huffman_tables(&mut Cursor::new(&input)).unwrap().len() + 1
+        );
+    }
+
+    #[test]
+    fn test_low_capacity() {
+        let input = TestImage::new(48, 32).build();
+        let write_data = write_secret(
+            &mut Cursor::new(&input),
+            &mut Vec::new(),
+            b"secret",
+            &WriteOptions::default(),
+        )
+        .unwrap();
+        assert!(!write_data.low_capacity);
+
+        let max = capacity(&mut Cursor::new(&input), &WriteOptions::default()).unwrap();
+        let options = WriteOptions {
+            min_capacity: Some(max + 1),
+            ..Default::default()
+        };
+        let error = write(&input, b"secret", &options).unwrap_err();
+        assert_eq!(
+            error.downcast_ref(),
+            Some(&StegError::LowCapacity { capacity: max })
+        );
+
+        // A single 8x8 grayscale block, with a DC table of seven 3-bit codes and an AC table of
+        // fifteen 4-bit codes, coded as just a zero DC difference and an EOB
+        let mut input = vec![0xFF, 0xD8];
+        let mut dqt = vec![0];
+        dqt.extend([1; 64]);
+        segment(&mut input, 0xDB, &dqt);
+        segment(&mut input, 0xC0, &[8, 0, 8, 0, 8, 1, 1, 0x11, 0]);
+        let mut dht = vec![0x00, 0, 0, 7, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        dht.extend(0..7);
+        dht.extend([0x10, 0, 0, 0, 15, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        dht.extend(0..15);
+        segment(&mut input, 0xC4, &dht);
+        segment(&mut input, 0xDA, &[1, 1, 0x00, 0, 63, 0]);
+        input.extend([0b0000_0001, 0xFF, 0xD9]);
+
+        let max = capacity(&mut Cursor::new(&input), &WriteOptions::default()).unwrap();
+        assert!(max > 0 && max < LOW_CAPACITY);
+        let write_data = write_secret(
+            &mut Cursor::new(&input),
+            &mut Vec::new(),
+            vec![0x55; max],
+            &WriteOptions::default(),
+        )
+        .unwrap();
+        assert!(write_data.low_capacity);
+    }
+
+    #[test]
+    fn test_quick_detect() {
+        let detect = |data: &[u8]| quick_detect(data).unwrap();
+
+        let input = TestImage::new(48, 32).build();
+        assert!(!detect(&input));
+        assert!(detect(
+            &write(&input, b"secret", &WriteOptions::default()).unwrap()
+        ));
+        assert!(detect(
+            &write(&input, b"", &WriteOptions::default()).unwrap()
+        ));
+
+        for options in [
+            WriteOptions {
+                fill_remaining: Some(7),
+                ..Default::default()
+            },
+            WriteOptions {
+                minimize_reorder: true,
+                least_changed_first: true,
+                ..Default::default()
+            },
+            WriteOptions {
+                ecc: Some(EccParams {
+                    data_shards: 1,
+                    parity_shards: 1,
+                }),
+                ..Default::default()
+            },
+        ] {
+            assert!(detect(&write(&input, b"secret", &options).unwrap()));
+        }
+
+        let input = TestImage::new(48, 32).progressive().build();
+        assert!(!detect(&input));
+        assert!(quick_detect(b"not a jpeg").is_err());
+    }
+
+    #[test]
+    fn test_strip_secret() {
+        let strip = |input: &[u8]| {
+            let mut output = Vec::new();
+            let changed = strip_secret(&mut Cursor::new(input), &mut output).unwrap();
+            (output, changed)
+        };
+
+        let input = TestImage::new(48, 32).build();
+        let embedded = write(&input, b"secret", &WriteOptions::default()).unwrap();
+        let (stripped, changed) = strip(&embedded);
+        assert!(changed);
+        assert_eq!(read(&stripped), None);
+        assert_eq!(peek_secret_len(&mut Cursor::new(&stripped)).unwrap(), None);
+        assert_eq!(decode_pixels(&stripped), decode_pixels(&input));
+        assert!(table_permutations(&mut Cursor::new(&stripped))
+            .unwrap()
+            .iter()
+            .all(|permutation| permutation.iter().enumerate().all(|(i, &p)| i == p)));
+
+        // Stripping again finds nothing to change
+        let (restripped, changed) = strip(&stripped);
+        assert!(!changed);
+        assert_eq!(restripped, stripped);
+    }
+
+    #[cfg(feature = "std-fs")]
+    #[test]
+    fn test_capacity_histogram() {
+        let dir = std::env::temp_dir().join(format!("capacity-histogram-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let image = dir.join("image.jpg");
+        std::fs::write(&image, TestImage::new(48, 32).build()).unwrap();
+        let not_a_jpeg = dir.join("notes.txt");
+        std::fs::write(&not_a_jpeg, b"not a jpeg").unwrap();
+        let missing = dir.join("missing.jpg");
+
+        let capacities = capacity_histogram(&[image.clone(), not_a_jpeg, missing]);
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let expected = capacity(
+            &mut Cursor::new(TestImage::new(48, 32).build()),
+            &Default::default(),
+        )
+        .unwrap();
+        assert_eq!(
+            capacities[0],
+            FileCapacity {
+                path: image,
+                capacity: expected,
+                error: None,
+            }
+        );
+
+        assert_eq!(capacities.len(), 3);
+        for entry in &capacities[1..] {
+            assert_eq!(entry.capacity, 0);
+            assert!(entry.error.is_some());
+        }
+        assert_eq!(
+            capacities[1].error.as_deref(),
+            Some(StegError::NotAJpeg.to_string().as_str())
+        );
+    }
+
+    #[test]
+    fn test_biguint() {
+        let input = TestImage::new(48, 32).build();
+        let jpeg = Jpeg::read_segments(&mut Cursor::new(&input)).unwrap();
+        let (table_sizes, _) = scan_table_data(&read_tables(&jpeg).unwrap());
+        let max_value = table_sizes.max_base_value();
+
+        let write = |value: &BigUint| {
+            let mut output = Vec::new();
+            write_secret_biguint(&mut Cursor::new(&input), &mut output, value).map(|_| output)
+        };
+        let read = |input: &[u8]| read_secret_biguint(&mut Cursor::new(input)).unwrap();
+
+        // Sorted tables hold zero
+        assert_eq!(read(&input), Some(BigUint::zero()));
+
+        for value in [
+            BigUint::from(0xBEEFu32),
+            BigUint::from(1u8),
+            &max_value - 1u8,
+        ] {
+            let output = write(&value).unwrap();
+            assert_eq!(read(&output), Some(value));
+            assert_eq!(decode_pixels(&output), decode_pixels(&input));
+        }
+
+        let error = write(&max_value).unwrap_err();
+        assert!(matches!(
+            error.downcast_ref(),
+            Some(StegError::PayloadTooLarge { .. })
+        ));
+
+        let no_tables = TestImage::new(48, 32).without_dht().build();
+        assert_eq!(read(&no_tables), None);
+    }
+
+    #[test]
+    fn test_key() {
+        let keyed = |key: &[u8]| WriteOptions {
+            key: Some(key.to_vec()),
+            ..Default::default()
+        };
+        let read_with_key =
+            |input: &[u8], key: &[u8]| read_secret_with_key(&mut Cursor::new(input), key).unwrap();
+
+        let input = TestImage::new(48, 32).build();
+        let jpeg = Jpeg::read_segments(&mut Cursor::new(&input)).unwrap();
+        let (table_sizes, _) = scan_table_data(&read_tables(&jpeg).unwrap());
+        let order = keyed_order(&table_sizes, b"key");
+        assert_eq!(order, keyed_order(&table_sizes, b"key"));
+        assert!(!table_orders(&table_sizes, None).contains(&order));
+
+        let output = write(&input, b"secret", &keyed(b"key")).unwrap();
+        assert_eq!(read_with_key(&output, b"key"), Some(b"secret".to_vec()));
+        assert_eq!(read_with_key(&output, b"other key"), None);
+        assert_eq!(read(&output), None);
+        assert_eq!(decode_pixels(&output), decode_pixels(&input));
+
+        // Nor does the key find a secret written without it
+        let unkeyed = write(&input, b"secret", &WriteOptions::default()).unwrap();
+        assert_eq!(read_with_key(&unkeyed, b"key"), None);
+
+        let options = WriteOptions {
+            minimize_reorder: true,
+            ..keyed(b"key")
+        };
+        let output = write(&input, b"secret", &options).unwrap();
+        assert_eq!(read_with_key(&output, b"key"), Some(b"secret".to_vec()));
+
+        let options = WriteOptions {
+            ecc: Some(EccParams {
+                data_shards: 1,
+                parity_shards: 1,
+            }),
+            ..keyed(b"key")
+        };
+        assert!(write(&input, b"secret", &options).is_err());
+    }
+
+    #[test]
+    fn test_non_interleaved() {
+        let subsampled = [(1, 2, 2), (2, 1, 1), (3, 1, 1)];
+        let images = [
+            // A single component is never interleaved, whatever its sampling factors
+            TestImage::new(37, 19).components(&[(1, 2, 2)]),
+            TestImage::new(37, 19)
+                .components(&subsampled)
+                .non_interleaved(),
+            TestImage::new(37, 19)
+                .components(&subsampled)
+                .non_interleaved()
+                .restart_interval(4),
+        ];
+
+        for image in images {
+            let input = image.build();
+            let output = write(&input, b"secret", &WriteOptions::default()).unwrap();
+            assert_eq!(read(&output), Some(b"secret".to_vec()));
+            assert_eq!(decode_pixels(&output), decode_pixels(&input));
+        }
+    }
+
+    #[test]
+    fn test_redefined_table() {
+        let input = TestImage::new(48, 32).build();
+        let jpeg = Jpeg::read_segments(&mut Cursor::new(&input)).unwrap();
+        let ac_luminance = read_tables(&jpeg).unwrap().remove(1).0;
+        let original_values = ac_luminance.values.clone();
+
+        // The file's AC luminance table, sent again in a DHT of its own ahead of the scan
+        let mut redefined = input.clone();
+        let dht = DhtData {
+            tables: vec![ac_luminance],
+        };
+        insert_segment(&mut redefined, 0xDA, 0, 0xC4, &dht.to_vec());
+
+        // Only the definition the scan decodes with counts, as for writing
+        let jpeg = Jpeg::read_segments(&mut Cursor::new(&redefined)).unwrap();
+        let in_scan = read_tables(&jpeg)
+            .unwrap()
+            .into_iter()
+            .map(|(_, in_scan)| in_scan)
+            .collect::<Vec<_>>();
+        assert_eq!(in_scan, [true, false, true, true, true]);
+        assert_eq!(capacity_of(&redefined), capacity_of(&input));
+
+        let max = capacity(&mut Cursor::new(&input), &Default::default()).unwrap();
+        let secret = vec![0xA5; max];
+        let output = write(&redefined, &secret, &WriteOptions::default()).unwrap();
+        assert_eq!(read(&output), Some(secret));
+        assert_eq!(decode_pixels(&output), decode_pixels(&input));
+
+        // The replaced definition is left as it was
+        let jpeg = Jpeg::read_segments(&mut Cursor::new(&output)).unwrap();
+        let tables = read_tables(&jpeg).unwrap();
+        assert_eq!(tables[1].0.values, original_values);
+        assert_ne!(tables[4].0.values, original_values);
+    }
+
+    #[test]
+    fn test_inject_default_tables() {
+        let input = TestImage::new(48, 32).without_dht().build();
+        assert!(write(&input, b"secret", &WriteOptions::default()).is_err());
+
+        let options = WriteOptions {
+            inject_default_tables: true,
+            ..Default::default()
+        };
+        let output = write(&input, b"secret", &options).unwrap();
+        assert_eq!(read(&output), Some(b"secret".to_vec()));
+        assert_eq!(decode_pixels(&input), decode_pixels(&output));
+    }
+
+    #[test]
+    fn test_minimize_reorder() {
+        let tables_of = |data: &[u8]| {
+            let jpeg = Jpeg::read_segments(&mut Cursor::new(data)).unwrap();
+            scan_table_data(&read_tables(&jpeg).unwrap()).1
+        };
+        let options = WriteOptions {
+            minimize_reorder: true,
+            ..Default::default()
+        };
+
+        // A carrier whose tables have all been shuffled out of sorted order
+        let input = TestImage::new(48, 32).build();
+        let mut shuffled = Vec::new();
+        let mut jpeg = Jpeg::read_segments(&mut Cursor::new(&input)).unwrap();
+        jpeg.process_segments_mut(DhtWriter::new(
+            &mut shuffled,
+            |table: &mut HuffmanTableData| table.values.reverse(),
+        ))
+        .unwrap();
+        assert_eq!(read(&shuffled), None);
+
+        let output = write(&shuffled, b"secret", &options).unwrap();
+        assert_eq!(read(&output), Some(b"secret".to_vec()));
+        assert_eq!(peek_secret_len(&mut Cursor::new(&output)).unwrap(), Some(6));
+
+        let (before, after) = (tables_of(&shuffled), tables_of(&output));
+        assert_eq!(before[..before.len() - 1], after[..after.len() - 1]);
+        assert_ne!(before.last(), after.last());
+
+        // Without the option, every table is rewritten
+        let output = write(&shuffled, b"secret", &WriteOptions::default()).unwrap();
+        assert_ne!(tables_of(&shuffled)[0], tables_of(&output)[0]);
+
+        // A secret that needs every table still fits
+        let secret = vec![0x55; capacity_of(&input) - 3];
+        assert_eq!(
+            read(&write(&shuffled, &secret, &options).unwrap()),
+            Some(secret.clone())
+        );
+
+        // Tables still holding the start of an older secret get overwritten, rather than left to
+        // be read back in place of the new one
+        let old = write(&input, &secret, &WriteOptions::default()).unwrap();
+        assert_eq!(
+            read(&write(&old, b"new", &options).unwrap()),
+            Some(b"new".to_vec())
+        );
+    }
+
+    #[test]
+    fn test_twelve_bit() {
+        let input = TestImage::new(48, 32).twelve_bit().build();
+        let output = write(&input, b"secret", &WriteOptions::default()).unwrap();
+        assert_eq!(read(&output), Some(b"secret".to_vec()));
+
+        // The same stream is out of range for 8-bit samples
+        let mut input = input;
+        let sof = input
+            .windows(2)
+            .position(|bytes| bytes == [0xFF, 0xC1])
+            .unwrap();
+        input[sof + 4] = 8;
+        let error = write(&input, b"secret", &WriteOptions::default()).unwrap_err();
+        assert!(error.to_string().contains("for 8-bit samples"));
+    }
+
+    #[test]
+    fn test_permutation_space() {
+        assert_eq!(permutation_space(&[]), BigUint::from(1u32));
+        assert_eq!(permutation_space(&[0, 1, 2, 3]), BigUint::from(12u32));
+
+        let input = TestImage::new(48, 32).build();
+        let jpeg = Jpeg::read_segments(&mut Cursor::new(&input)).unwrap();
+        let (table_sizes, _) = scan_table_data(&read_tables(&jpeg).unwrap());
+        let spaces = table_sizes
+            .iter()
+            .map(|sizes| {
+                permutation_space(
+                    &sizes
+                        .iter()
+                        .map(|&count| count as usize)
+                        .collect::<Vec<_>>(),
+                )
+            })
+            .product::<BigUint>();
+        assert_eq!(spaces, table_sizes.max_base_value());
+    }
+
+    #[test]
+    fn test_component_ids() {
+        // Zero-based, and the 'R', 'G', 'B' ids some encoders use
+        for ids in [[0, 1, 2], [82, 71, 66]] {
+            let input = TestImage::new(37, 19)
+                .components(&[(ids[0], 2, 2), (ids[1], 1, 1), (ids[2], 1, 1)])
+                .restart_interval(2)
+                .build();
+
+            let output = write(&input, b"secret", &WriteOptions::default()).unwrap();
+            assert_eq!(read(&output), Some(b"secret".to_vec()));
+            assert_eq!(decode_pixels(&output), decode_pixels(&input));
+
+            let jpeg = Jpeg::read_segments(&mut Cursor::new(&output)).unwrap();
+            let grid = jpeg.decode_coefficients().unwrap();
+            let grid_ids = grid.components.iter().map(|c| c.component_id);
+            assert_eq!(grid_ids.collect::<Vec<_>>(), ids.map(u32::from));
+        }
+    }
+
+    #[test]
+    fn test_deterministic_output() {
+        for input in [
+            TestImage::new(48, 32).build(),
+            TestImage::new(37, 19)
+                .components(&[(1, 2, 2), (2, 1, 1), (3, 1, 1)])
+                .restart_interval(2)
+                .build(),
+        ] {
+            let output = write(&input, b"secret", &WriteOptions::default()).unwrap();
+            assert_eq!(
+                output,
+                write(&input, b"secret", &WriteOptions::default()).unwrap()
+            );
+
+            let carrier =
+                PreparedCarrier::new(&mut Cursor::new(&input), &Default::default()).unwrap();
+            assert_eq!(output, carrier.embed(b"secret").unwrap());
+            assert_eq!(output, carrier.embed(b"secret").unwrap());
+        }
+    }
+
+    #[test]
+    fn test_write_to_slice() {
+        let input = TestImage::new(48, 32).build();
+        let expected = write(&input, b"secret", &WriteOptions::default()).unwrap();
+
+        let mut out = vec![0; input.len() + 16];
+        let written = write_secret_to_slice(&input, b"secret", &mut out).unwrap();
+        assert_eq!(&out[..written], expected);
+
+        let mut out = vec![0; expected.len()];
+        assert_eq!(
+            write_secret_to_slice(&input, b"secret", &mut out).unwrap(),
+            expected.len()
+        );
+
+        let mut out = vec![0; expected.len() - 1];
+        let error = write_secret_to_slice(&input, b"secret", &mut out).unwrap_err();
+        assert_eq!(error.downcast_ref(), Some(&StegError::BufferTooSmall));
+
+        let error = write_secret_to_slice(b"not a jpeg", b"secret", &mut out).unwrap_err();
+        assert_eq!(error.downcast_ref(), Some(&StegError::NotAJpeg));
+    }
+
+    #[test]
+    fn test_read_from_mmap() {
+        let output = write(
+            &TestImage::new(48, 32).build(),
+            b"secret",
+            &Default::default(),
+        )
+        .unwrap();
+        assert_eq!(
+            read_secret_from_mmap(&output).unwrap(),
+            Some(b"secret".to_vec())
+        );
+
+        // Read in full, as the tables may change between scans
+        let input = TestImage::new(37, 19).progressive().build();
+        assert_eq!(
+            read_secret_from_mmap(&input).unwrap(),
+            read_secret(&mut Cursor::new(&input)).unwrap()
+        );
+
+        let error = read_secret_from_mmap(b"not a jpeg").unwrap_err();
+        assert_eq!(error.downcast_ref(), Some(&StegError::NotAJpeg));
+    }
+
+    #[test]
+    fn test_not_a_jpeg() {
+        let png = [
+            0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A, 0x00, 0x00, 0x00, 0x0D,
+        ];
+        let mut random = vec![0u8; 4096];
+        let mut rng = SplitMix64(3);
+        for chunk in random.chunks_mut(8) {
+            chunk.copy_from_slice(&rng.next().to_be_bytes());
+        }
+        // Even with stray markers scattered through it
+        random[100..102].copy_from_slice(&[0xFF, 0xC4]);
+        random[200..202].copy_from_slice(&[0xFF, 0xD9]);
+
+        for input in [&png[..], &random, &[], &[0xFF]] {
+            let not_a_jpeg =
+                |error: anyhow::Error| error.downcast_ref() == Some(&StegError::NotAJpeg);
+            assert!(not_a_jpeg(
+                read_secret(&mut Cursor::new(input)).unwrap_err()
+            ));
+            assert!(not_a_jpeg(
+                capacity(&mut Cursor::new(input), &Default::default()).unwrap_err()
+            ));
+            assert!(not_a_jpeg(
+                write(input, b"secret", &Default::default()).unwrap_err()
+            ));
+        }
+    }
+
+    #[test]
+    fn test_table_permutations() {
+        let permutations = |data: &[u8]| table_permutations(&mut Cursor::new(data)).unwrap();
+        let is_identity =
+            |permutation: &Vec<usize>| permutation.iter().enumerate().all(|(i, &p)| i == p);
+
+        let input = TestImage::new(48, 32).build();
+        let before = permutations(&input);
+        assert_eq!(before.len(), 4);
+        assert!(before.iter().all(is_identity));
+
+        let output = write(
+            &input,
+            &vec![0x55; capacity_of(&input) - 3],
+            &Default::default(),
+        )
+        .unwrap();
+        let after = permutations(&output);
+        assert!(after.iter().all(|permutation| !is_identity(permutation)));
+
+        // Applying each permutation to the sorted values gives back the table
+        let jpeg = Jpeg::read_segments(&mut Cursor::new(&output)).unwrap();
+        let (table_sizes, table_values) = scan_table_data(&read_tables(&jpeg).unwrap());
+        for ((sizes, values), permutation) in table_sizes.iter().zip(&table_values).zip(&after) {
+            let sizes = sizes
+                .iter()
+                .map(|&count| count as usize)
+                .collect::<Vec<_>>();
+            let mut sorted = Vec::new();
+            for bucket in split_table_values(&sizes, values) {
+                let mut bucket = bucket.to_vec();
+                bucket.sort();
+                sorted.extend(bucket);
+            }
+            let rebuilt = permutation.iter().map(|&p| sorted[p]).collect::<Vec<_>>();
+            assert_eq!(&rebuilt, values);
+        }
+    }
+
+    #[test]
+    fn test_secret_multi() {
+        let inputs = [
+            TestImage::new(48, 32).build(),
+            TestImage::new(16, 16).components(&[(1, 1, 1)]).build(),
+            TestImage::new(48, 32).restart_interval(2).build(),
+        ];
+        let combined = inputs.iter().map(|input| capacity_of(input)).sum::<usize>();
+        let secret = (0..combined - 30).map(|i| i as u8).collect::<Vec<_>>();
+
+        let write_multi = |inputs: &[Vec<u8>], secret: &[u8]| {
+            let mut readers = inputs.iter().map(Cursor::new).collect::<Vec<_>>();
+            let mut outputs = vec![Vec::new(); inputs.len()];
+            let mut carriers = readers
+                .iter_mut()
+                .zip(&mut outputs)
+                .map(|(reader, output)| (reader as &mut dyn Read, output as &mut dyn Write))
+                .collect::<Vec<_>>();
+            write_secret_multi(&mut carriers, secret).map(|_| outputs)
+        };
+        let read_multi = |outputs: &[&Vec<u8>]| {
+            let mut readers = outputs.iter().map(Cursor::new).collect::<Vec<_>>();
+            let mut readers = readers
+                .iter_mut()
+                .map(|reader| reader as &mut dyn Read)
+                .collect::<Vec<_>>();
+            read_secret_multi(&mut readers)
+        };
+
+        let outputs = write_multi(&inputs, &secret).unwrap();
+        assert_eq!(
+            read_multi(&[&outputs[0], &outputs[1], &outputs[2]]).unwrap(),
+            secret
+        );
+
+        // A short secret leaves the later carriers with empty parts
+        let short = write_multi(&inputs, b"secret").unwrap();
+        assert_eq!(
+            read_multi(&[&short[0], &short[1], &short[2]]).unwrap(),
+            b"secret"
+        );
+
+        assert!(write_multi(&inputs, &vec![0x55; combined]).is_err());
+        assert!(read_multi(&[&outputs[0], &outputs[2], &outputs[1]]).is_err());
+        assert!(read_multi(&[&outputs[0], &outputs[1]]).is_err());
+        assert!(read_multi(&[&outputs[0], &inputs[1], &outputs[2]]).is_err());
+    }
+
+    #[test]
+    fn test_least_changed_first() {
+        let options = WriteOptions {
+            least_changed_first: true,
+            ..Default::default()
+        };
+        let input = TestImage::new(48, 32).build();
+        let jpeg = Jpeg::read_segments(&mut Cursor::new(&input)).unwrap();
+        let (table_sizes, _) = scan_table_data(&read_tables(&jpeg).unwrap());
+
+        // DC chrominance, DC luminance, AC chrominance, AC luminance
+        assert_eq!(NS2::least_changed_order(&table_sizes), [2, 0, 3, 1]);
+
+        let changed = |output: &[u8]| {
+            table_permutations(&mut Cursor::new(output))
+                .unwrap()
+                .iter()
+                .map(|permutation| permutation.iter().enumerate().any(|(i, &p)| i != p))
+                .collect::<Vec<_>>()
+        };
+
+        // Fits the AC luminance table alone, but not the AC chrominance table that would otherwise
+        // take the least significant digit
+        let report = table_report(&mut Cursor::new(&input)).unwrap();
+        let secret =
+            vec![0x55; (report[1].bits_of_capacity as usize - 8 * SECRET_HEADER.len()) / 8];
+        let output = write(&input, &secret, &options).unwrap();
+        assert_eq!(read(&output), Some(secret.clone()));
+        assert_eq!(
+            peek_secret_len(&mut Cursor::new(&output)).unwrap(),
+            Some(secret.len())
+        );
+        assert_eq!(changed(&output), [false, true, false, false]);
+
+        let output = write(&input, &secret, &Default::default()).unwrap();
+        assert!(changed(&output)[3]);
+
+        // A secret needing every table reads back too
+        let secret = vec![0x55; capacity_of(&input) - 3];
+        let output = write(&input, &secret, &options).unwrap();
+        assert_eq!(read(&output), Some(secret));
+    }
 }
@@ -1,21 +1,41 @@
-mod fns;
-mod huffman;
-mod jpeg;
-mod lib_secret;
-mod processors;
-mod rw_stream;
+use hackathon_jpeg_steganography::{jpeg, lib_secret, processors, EccParams};
 
 fn main() -> anyhow::Result<()> {
     use clap::{arg, command, Command};
 
     let matches = command!()
         .arg(arg!(path: <PATH> "Image path"))
+        .arg(arg!(--json "Print the segment debug information as JSON, one object per line"))
         .subcommand(
             Command::new("write")
                 .arg(arg!(output: <OUTPUT> "Output path"))
-                .arg(arg!(secret: <SECRET> "Secret phrase")),
+                .arg(arg!(secret: <SECRET> "Secret phrase"))
+                .arg(arg!(--"inject-default-tables" "Add explicit DHT segments to files without any"))
+                .arg(
+                    arg!(--"fill-seed" <SEED> "Fill the unused capacity with random data from this seed")
+                        .required(false)
+                        .value_parser(clap::value_parser!(u64)),
+                )
+                .arg(arg!(--"minimize-reorder" "Leave the tables a short secret doesn't need in their original order"))
+                .arg(arg!(--"least-changed-first" "Fill the tables with the most capacity first, leaving smaller ones sorted"))
+                .arg(
+                    arg!(--ecc <SHARDS> "Split the secret into data and parity shards, given as DATA,PARITY (e.g. 2,1)")
+                        .required(false),
+                )
+                .arg(arg!(--"same-size" "Pad the output to the same length as the input"))
+                .arg(arg!(--key <KEY> "Shuffle which tables carry the secret by this key").required(false))
+                .arg(
+                    arg!(--"min-capacity" <BYTES> "Refuse images that can hold fewer bytes than this")
+                        .required(false)
+                        .value_parser(clap::value_parser!(usize)),
+                ),
         )
-        .subcommand(Command::new("read"))
+        .subcommand(
+            Command::new("read")
+                .arg(arg!(--key <KEY> "The key the secret was written with").required(false))
+                .arg(arg!(--output <OUTPUT> "Save the secret's raw bytes to this path").required(false)),
+        )
+        .subcommand(Command::new("capacity"))
         .get_matches();
 
     let in_path = matches.get_one::<String>("path").unwrap();
@@ -23,20 +43,56 @@ fn main() -> anyhow::Result<()> {
     if let Some(matches) = matches.subcommand_matches("write") {
         let out_path = matches.get_one::<String>("output").unwrap();
         let secret = matches.get_one::<String>("secret").unwrap();
-        write_secret_to_file(in_path, out_path, secret)?;
-    } else if matches.subcommand_matches("read").is_some() {
-        read_secret_from_file(in_path)?;
+        let options = lib_secret::WriteOptions {
+            inject_default_tables: matches.contains_id("inject-default-tables"),
+            fill_remaining: matches.get_one::<u64>("fill-seed").copied(),
+            minimize_reorder: matches.contains_id("minimize-reorder"),
+            least_changed_first: matches.contains_id("least-changed-first"),
+            ecc: matches
+                .get_one::<String>("ecc")
+                .map(|shards| parse_ecc(shards))
+                .transpose()?,
+            pad_to_len: match matches.contains_id("same-size") {
+                true => Some(std::fs::metadata(in_path)?.len() as usize),
+                false => None,
+            },
+            key: matches
+                .get_one::<String>("key")
+                .map(|key| key.as_bytes().to_vec()),
+            min_capacity: matches.get_one::<usize>("min-capacity").copied(),
+            on_table_fill: None,
+        };
+        write_secret_to_file(in_path, out_path, secret, &options)?;
+    } else if let Some(matches) = matches.subcommand_matches("read") {
+        read_secret_from_file(
+            in_path,
+            matches.get_one::<String>("key"),
+            matches.get_one::<String>("output"),
+        )?;
+    } else if matches.subcommand_matches("capacity").is_some() {
+        show_capacity_of_file(in_path)?;
     } else {
-        debug_file(in_path)?;
+        debug_file(in_path, matches.contains_id("json"))?;
     }
 
     Ok(())
 }
 
+fn parse_ecc(shards: &str) -> anyhow::Result<EccParams> {
+    match shards.split_once(',') {
+        Some((data, parity)) => Ok(EccParams {
+            data_shards: data.trim().parse()?,
+            parity_shards: parity.trim().parse()?,
+        }),
+        None => anyhow::bail!("Expected --ecc as DATA,PARITY, but got '{shards}'"),
+    }
+}
+
 fn write_secret_to_file<P: AsRef<std::path::Path>, S: AsRef<str>>(
     in_file: P,
     out_file: P,
     secret: S,
+    options: &lib_secret::WriteOptions,
 ) -> anyhow::Result<()> {
     use std::fs::File;
     use std::io::{BufReader, BufWriter, Cursor, Write};
@@ -46,8 +102,12 @@ fn write_secret_to_file<P: AsRef<std::path::Path>, S: AsRef<str>>(
 
     let out_data = Vec::<u8>::new();
     let mut writer = Cursor::new(out_data);
-    let write_data =
-        lib_secret::write_secret(&mut reader, &mut writer, secret.as_ref().as_bytes())?;
+    let write_data = lib_secret::write_secret(
+        &mut reader,
+        &mut writer,
+        secret.as_ref().as_bytes(),
+        options,
+    )?;
 
     let out_data = writer.into_inner();
     let mut out_file = BufWriter::new(File::create(out_file)?);
@@ -57,35 +117,79 @@ fn write_secret_to_file<P: AsRef<std::path::Path>, S: AsRef<str>>(
         "Secret uses ~{} / {} bytes of re-arranged Huffman tables",
         write_data.secret_size, write_data.approx_max_size
     );
+    println!("Fill ratio: {:.3e}", write_data.fill_ratio);
+    if write_data.low_capacity {
+        println!("Warning: this image has very little capacity, so only short secrets will fit");
+    }
+    if let Some(tables) = write_data.lost_tables_tolerated {
+        println!("Secret survives any {tables} tables being re-ordered");
+    }
     println!("Wrote secret in {} ms", start.elapsed().as_millis());
     Ok(())
 }
 
-fn read_secret_from_file<P: AsRef<std::path::Path>>(in_file: P) -> anyhow::Result<()> {
+fn read_secret_from_file<P: AsRef<std::path::Path>>(
+    in_file: P,
+    key: Option<&String>,
+    out_file: Option<&String>,
+) -> anyhow::Result<()> {
     use std::fs::File;
     use std::io::BufReader;
 
     let mut reader = BufReader::new(File::open(in_file)?);
-    match lib_secret::read_secret(&mut reader)? {
-        None => {
+    let secret = match key {
+        None => lib_secret::read_secret(&mut reader)?,
+        Some(key) => lib_secret::read_secret_with_key(&mut reader, key.as_bytes())?,
+    };
+    match (secret, out_file) {
+        (None, _) => {
             println!("No message found within file");
         }
-        Some(secret) => {
-            println!("Secret: '{}'", String::from_utf8(secret)?);
+        (Some(secret), Some(out_file)) => {
+            std::fs::write(out_file, &secret)?;
+            println!("Saved {} bytes of secret to {out_file}", secret.len());
         }
+        (Some(secret), None) => match String::from_utf8(secret) {
+            Ok(secret) => println!("Secret: '{secret}'"),
+            Err(error) => println!(
+                "Extracted {} bytes of binary data; use --output to save",
+                error.as_bytes().len()
+            ),
+        },
     }
 
     Ok(())
 }
 
-fn debug_file<P: AsRef<std::path::Path>>(in_file: P) -> anyhow::Result<()> {
+fn show_capacity_of_file<P: AsRef<std::path::Path>>(in_file: P) -> anyhow::Result<()> {
+    use std::io::Cursor;
+
+    let data = std::fs::read(in_file)?;
+    let capacity = lib_secret::capacity(&mut Cursor::new(&data), &Default::default())?;
+    println!("Maximum message length: {capacity} bytes");
+
+    for table in lib_secret::table_report(&mut Cursor::new(&data))? {
+        let class = if table.table_class == 0 { "DC" } else { "AC" };
+        println!(
+            "\t{class} table {}: {} re-arrangeable values, {} bits",
+            table.table_index, table.usable_values, table.bits_of_capacity
+        );
+    }
+
+    Ok(())
+}
+
+fn debug_file<P: AsRef<std::path::Path>>(in_file: P, json: bool) -> anyhow::Result<()> {
     use std::fs::File;
     use std::io::BufReader;
 
     let mut reader = BufReader::new(File::open(in_file)?);
     let jpeg = jpeg::Jpeg::read_segments(&mut reader)?;
 
-    jpeg.process_segments(processors::DebugReader::new(|msg| println!("{}", msg)))?;
+    match json {
+        true => jpeg.process_segments(processors::JsonReader::new(|value| println!("{value}")))?,
+        false => jpeg.process_segments(processors::DebugReader::new(|msg| println!("{}", msg)))?,
+    }
 
     Ok(())
 }
@@ -1,6 +1,6 @@
 use anyhow::Result;
 
-use crate::jpeg::{segments::*, Jpeg, Marker::*, ProcessSegment, Segment};
+use crate::jpeg::{segments::*, Jpeg, Marker::*, ParsedSegment, ProcessSegment, Segment};
 
 pub struct DebugReader<F> {
     log: F,
@@ -20,11 +20,7 @@ macro_rules! log {
 
 impl<F: Fn(String)> ProcessSegment for DebugReader<F> {
     fn process_segment(&self, _: &Jpeg, segment: &Segment) -> Result<()> {
-        let Segment {
-            index,
-            marker,
-            data,
-        } = segment;
+        let Segment { index, marker, .. } = segment;
         let marker = *marker;
 
         if let RST(_) = marker {
@@ -33,20 +29,18 @@ impl<F: Fn(String)> ProcessSegment for DebugReader<F> {
 
         log!(
             self.log,
-            "[{index:04X}] FF{:02X} {marker:?}",
+            "[{index:04X}] FF{:02X} {marker:?} ({marker})",
             Into::<u8>::into(marker),
         );
 
-        match marker {
+        match segment.parse()? {
             // [SPEC] B.2.2 -- Frame header syntax
-            SOF0 | SOF1 | SOF2 => {
-                let SofData {
-                    precision,
-                    width,
-                    height,
-                    components,
-                } = SofData::try_from(&data[..])?;
-
+            ParsedSegment::Frame(SofData {
+                precision,
+                width,
+                height,
+                components,
+            }) => {
                 log!(self.log, "\tStart of Frame\n\tPrecision: {precision}, Width: {width}, Height: {height}, Num Components: {}",
                     components.len()
                 );
@@ -66,16 +60,14 @@ impl<F: Fn(String)> ProcessSegment for DebugReader<F> {
             }
 
             // [SPEC] B.2.3 -- Scan header syntax
-            SOS => {
-                let SosData {
-                    spectral_start,
-                    spectral_end,
-                    approx_high,
-                    approx_low,
-                    components,
-                    ..
-                } = SosData::try_from(&data[..])?;
-
+            ParsedSegment::Scan(SosData {
+                spectral_start,
+                spectral_end,
+                approx_high,
+                approx_low,
+                components,
+                ..
+            }) => {
                 log!(self.log, "\tStart of Scan");
                 for ScanComponentData {
                     component_id,
@@ -89,9 +81,8 @@ impl<F: Fn(String)> ProcessSegment for DebugReader<F> {
             }
 
             // [SPEC] B.2.4.1 -- Quantization table-specification syntax
-            DQT => {
+            ParsedSegment::Quantization(DqtData { tables }) => {
                 log!(self.log, "\tDefine Quantization Table");
-                let DqtData { tables } = DqtData::try_from(&data[..])?;
                 for (
                     index,
                     QuantizationTable {
@@ -107,9 +98,7 @@ impl<F: Fn(String)> ProcessSegment for DebugReader<F> {
             }
 
             // [SPEC] Table B.2.4.2 -- Huffman table-specification syntax
-            DHT => {
-                let DhtData { tables } = DhtData::try_from(&data[..])?;
-
+            ParsedSegment::Huffman(DhtData { tables }) => {
                 log!(self.log, "\tDefine Huffman Table");
                 for (
                     index,
@@ -126,9 +115,32 @@ impl<F: Fn(String)> ProcessSegment for DebugReader<F> {
                 log!(self.log, "");
             }
 
+            ParsedSegment::Raw(data) if marker == APP(0) => {
+                if let Ok(Jfif {
+                    version,
+                    units,
+                    x_density,
+                    y_density,
+                    thumbnail_width,
+                    thumbnail_height,
+                    ..
+                }) = Jfif::try_from(data)
+                {
+                    let units = match units {
+                        0 => "no units",
+                        1 => "dots per inch",
+                        2 => "dots per cm",
+                        _ => "unknown units",
+                    };
+                    log!(self.log, "\tJFIF {}.{:02}\n\tDensity: {x_density}x{y_density} ({units}), Thumbnail: {thumbnail_width}x{thumbnail_height}\n",
+                        version >> 8,
+                        version & 0xFF
+                    );
+                }
+            }
+
             // [SPEC] B.2.4.4 -- Restart interval definition syntax
-            DRI => {
-                let count = u16::from_be_bytes(data[0..2].try_into().unwrap());
+            ParsedSegment::RestartInterval(DriData { count }) => {
                 log!(self.log, "\tDefine Restart Interval: {count}\n");
             }
 
@@ -1,58 +1,169 @@
 use std::io::Write;
 
 use anyhow::{bail, Result};
-use bitstream_io::{
-    huffman::{compile_read_tree, compile_write_tree},
-    BigEndian,
-};
 
 use crate::{
-    huffman::construct_huffman_table,
-    jpeg::{process_entropy_stream, segments::*, Jpeg, Marker, ProcessSegmentMut, Segment},
+    jpeg::{process_entropy_stream_into, segments::*, Jpeg, Marker, ProcessSegmentMut, Segment},
     rw_stream::HuffmanRWTree,
 };
 
 pub struct DhtWriter<W: Write, F> {
     writer: W,
     callback: F,
+    scratch: Vec<u8>,
+    /// Whether a DRI segment has been written yet
+    wrote_dri: bool,
+    /// Whether any table written so far had its values changed
+    changed_tables: bool,
+    always_reencode: bool,
 }
 
 impl<W: Write, F> DhtWriter<W, F> {
     pub fn new(writer: W, callback: F) -> Self {
-        Self { writer, callback }
+        Self {
+            writer,
+            callback,
+            scratch: Vec::new(),
+            wrote_dri: false,
+            changed_tables: false,
+            always_reencode: false,
+        }
+    }
+
+    /// Has scans re-encoded even when no table they're coded with was changed, which otherwise
+    /// copies them through as they are
+    pub fn always_reencode(mut self) -> Self {
+        self.always_reencode = true;
+        self
+    }
+}
+
+impl<W: Write, G> DhtWriter<W, Batch<G>> {
+    /// As `new`, but `callback` is given every table in the file at once, in order, and returns
+    /// the new values of each
+    pub fn new_batch(writer: W, callback: G) -> Self {
+        Self::new(
+            writer,
+            Batch {
+                callback,
+                values: None,
+            },
+        )
+    }
+}
+
+/// How `DhtWriter` finds each table's new values
+pub trait TableCallback {
+    fn update(&mut self, jpeg: &Jpeg, table: &mut HuffmanTableData) -> Result<()>;
+}
+
+impl<F: Fn(&mut HuffmanTableData)> TableCallback for F {
+    fn update(&mut self, _: &Jpeg, table: &mut HuffmanTableData) -> Result<()> {
+        self(table);
+        Ok(())
+    }
+}
+
+/// The callback given to `DhtWriter::new_batch`, with the values it returned still to be handed out
+pub struct Batch<G> {
+    callback: G,
+    values: Option<std::vec::IntoIter<Vec<u8>>>,
+}
+
+impl<G: Fn(&[HuffmanTableData]) -> Vec<Vec<u8>>> TableCallback for Batch<G> {
+    fn update(&mut self, jpeg: &Jpeg, table: &mut HuffmanTableData) -> Result<()> {
+        if self.values.is_none() {
+            let mut tables = Vec::new();
+            for segment in jpeg.segments.iter().filter(|s| s.marker == Marker::DHT) {
+                tables.extend(DhtData::try_from(&segment.data[..])?.tables);
+            }
+
+            let values = (self.callback)(&tables);
+            if values.len() != tables.len() {
+                bail!(
+                    "Expected values for {} tables, but got {}",
+                    tables.len(),
+                    values.len()
+                );
+            }
+            self.values = Some(values.into_iter());
+        }
+
+        match self.values.as_mut().and_then(|values| values.next()) {
+            None => bail!("More tables were written than were read"),
+            Some(values) => table.values = values,
+        }
+        Ok(())
     }
 }
 
-impl<W: Write, F: Fn(&mut HuffmanTableData)> ProcessSegmentMut for DhtWriter<W, F> {
+impl<W: Write, F: TableCallback> ProcessSegmentMut for DhtWriter<W, F> {
     fn process_segment(&mut self, jpeg: &mut Jpeg, segment: &Segment) -> Result<()> {
         let mut segment = segment.clone();
         match segment.marker {
             Marker::DHT => {
                 let mut dht_data = DhtData::try_from(&segment.data[..])?;
                 for table in &mut dht_data.tables {
-                    let read_tree = compile_read_tree::<BigEndian, _>(construct_huffman_table(
-                        &table.sizes,
-                        &table.values,
-                    ))?;
+                    let read_values = table.values.clone();
+                    self.callback.update(jpeg, table)?;
+                    self.changed_tables |= table.values != read_values;
 
-                    (self.callback)(table);
+                    // A table without any codes carries nothing, and has no tree to build
+                    if table.sizes.iter().all(|&count| count == 0) {
+                        continue;
+                    }
 
-                    let write_tree = Box::new([compile_write_tree::<BigEndian, _>(
-                        construct_huffman_table(&table.sizes, &table.values),
-                    )?]);
-
-                    let rw_tree = HuffmanRWTree::new(read_tree, write_tree);
+                    let rw_tree =
+                        HuffmanRWTree::from_tables(&table.sizes, &read_values, &table.values)?;
                     jpeg.set_huffman_tree(table.table_class, table.table_index, rw_tree);
                 }
 
                 segment.data = dht_data.to_vec();
             }
 
+            Marker::DRI => {
+                if let Some(count) = jpeg.output_restart_interval {
+                    segment.data = dri_data(count)?;
+                }
+                self.wrote_dri = true;
+            }
+
             Marker::SOS => {
+                // A file without restarts needs a DRI segment ahead of its first scan to get them
+                match jpeg.output_restart_interval {
+                    Some(count) if count > 0 && !self.wrote_dri => {
+                        let dri = Segment {
+                            index: segment.index,
+                            marker: Marker::DRI,
+                            data: dri_data(count)?,
+                        };
+                        Jpeg::write_segment(&mut self.writer, &dri)?;
+                        self.wrote_dri = true;
+                    }
+                    _ => {}
+                }
+
                 if jpeg.scan.spectral_start != 0 || jpeg.scan.spectral_end != 64 {
                     bail!("Progressive JPEG files not supported")
                 }
-                jpeg.scan.image_data = process_entropy_stream(jpeg, &jpeg.scan.image_data)?;
+                // [SPEC] B.2.3 -- Successive approximation is meaningless outside progressive mode
+                if jpeg.scan.approx_high != 0 || jpeg.scan.approx_low != 0 {
+                    bail!("Sequential scan has nonzero successive approximation bits")
+                }
+
+                // Nothing would change in re-encoding a scan with the tables it was coded with
+                let same_restarts = match jpeg.output_restart_interval {
+                    Some(count) => count == jpeg.restart_interval,
+                    None => true,
+                };
+                if !self.always_reencode && !self.changed_tables && same_restarts {
+                    Jpeg::write_segment(&mut self.writer, &segment)?;
+                    return Ok(());
+                }
+
+                // The scan's old data becomes the buffer for the next one
+                process_entropy_stream_into(jpeg, &jpeg.scan.image_data, &mut self.scratch)?;
+                std::mem::swap(&mut jpeg.scan.image_data, &mut self.scratch);
                 segment.data = jpeg.scan.to_vec();
             }
 
@@ -60,6 +171,18 @@ impl<W: Write, F: Fn(&mut HuffmanTableData)> ProcessSegmentMut for DhtWriter<W,
         }
 
         Jpeg::write_segment(&mut self.writer, &segment)?;
+        if segment.marker == Marker::EOI {
+            self.writer.write_all(&jpeg.trailer)?;
+        }
+
         Ok(())
     }
 }
+
+// [SPEC] B.2.4.4 -- Ri is 16 bits
+fn dri_data(count: u32) -> Result<Vec<u8>> {
+    match u16::try_from(count) {
+        Ok(count) => Ok(count.to_be_bytes().to_vec()),
+        Err(_) => bail!("Restart interval {count} doesn't fit in a DRI segment"),
+    }
+}
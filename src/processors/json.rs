@@ -0,0 +1,173 @@
+use anyhow::Result;
+use serde_json::{json, Map, Value};
+
+use crate::jpeg::{segments::*, Jpeg, Marker::*, ProcessSegment, Segment};
+
+/// As `DebugReader`, but passes each segment's parsed fields on as a JSON object
+pub struct JsonReader<F> {
+    log: F,
+}
+
+impl<F> JsonReader<F> {
+    pub fn new(log: F) -> Self {
+        Self { log }
+    }
+}
+
+impl<F: Fn(Value)> ProcessSegment for JsonReader<F> {
+    fn process_segment(&self, _: &Jpeg, segment: &Segment) -> Result<()> {
+        let Segment {
+            index,
+            marker,
+            data,
+        } = segment;
+        let marker = *marker;
+
+        if let RST(_) = marker {
+            return Ok(());
+        }
+
+        let mut object = Map::new();
+        object.insert("index".into(), json!(index));
+        object.insert("marker".into(), json!(u8::from(marker)));
+        object.insert("name".into(), json!(format!("{marker:?}")));
+
+        let fields = match marker {
+            // [SPEC] B.2.2 -- Frame header syntax
+            SOF0 | SOF1 | SOF2 => {
+                let frame = SofData::try_from(&data[..])?;
+                let components = frame
+                    .components
+                    .iter()
+                    .map(|component| {
+                        json!({
+                            "component_id": component.component_id,
+                            "h_factor": component.h_factor,
+                            "v_factor": component.v_factor,
+                            "table_index": component.table_index,
+                        })
+                    })
+                    .collect::<Vec<_>>();
+
+                json!({
+                    "precision": frame.precision,
+                    "width": frame.width,
+                    "height": frame.height,
+                    "components": components,
+                })
+            }
+
+            // [SPEC] B.2.3 -- Scan header syntax
+            SOS => {
+                let scan = SosData::try_from(&data[..])?;
+                let components = scan
+                    .components
+                    .iter()
+                    .map(|component| {
+                        json!({
+                            "component_id": component.component_id,
+                            "dc_table_index": component.dc_table_index,
+                            "ac_table_index": component.ac_table_index,
+                        })
+                    })
+                    .collect::<Vec<_>>();
+
+                json!({
+                    "components": components,
+                    "spectral_start": scan.spectral_start,
+                    "spectral_end": scan.spectral_end,
+                    "approx_high": scan.approx_high,
+                    "approx_low": scan.approx_low,
+                })
+            }
+
+            // [SPEC] B.2.4.1 -- Quantization table-specification syntax
+            DQT => {
+                let tables = DqtData::try_from(&data[..])?
+                    .tables
+                    .into_iter()
+                    .map(|table| {
+                        json!({
+                            "precision": table.precision,
+                            "table_index": table.table_index,
+                            "values": table.values,
+                        })
+                    })
+                    .collect::<Vec<_>>();
+
+                json!({ "tables": tables })
+            }
+
+            // [SPEC] Table B.2.4.2 -- Huffman table-specification syntax
+            DHT => {
+                let tables = DhtData::try_from(&data[..])?
+                    .tables
+                    .into_iter()
+                    .map(|table| {
+                        json!({
+                            "table_class": table.table_class,
+                            "table_index": table.table_index,
+                            "sizes": table.sizes,
+                            "values": table.values,
+                        })
+                    })
+                    .collect::<Vec<_>>();
+
+                json!({ "tables": tables })
+            }
+
+            // [SPEC] B.2.4.4 -- Restart interval definition syntax
+            DRI => json!({ "count": DriData::try_from(&data[..])?.count }),
+
+            _ => json!({}),
+        };
+
+        if let Value::Object(fields) = fields {
+            object.extend(fields);
+        }
+
+        (self.log)(Value::Object(object));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::jpeg::test_image::TestImage;
+
+    #[test]
+    fn test_json_reader() -> Result<()> {
+        let input = TestImage::new(48, 32).restart_interval(2).build();
+        let jpeg = Jpeg::read_segments(&mut Cursor::new(&input))?;
+
+        let values = RefCell::new(Vec::new());
+        jpeg.process_segments(JsonReader::new(|value| values.borrow_mut().push(value)))?;
+        let values = values.into_inner();
+
+        let names = values
+            .iter()
+            .map(|value| value["name"].as_str().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            ["SOI", "APP(0)", "DQT", "SOF0", "DHT", "DRI", "SOS", "EOI"]
+        );
+
+        let frame = &values[3];
+        assert_eq!(frame["marker"], 0xC0);
+        assert_eq!(frame["width"], 48);
+        assert_eq!(frame["height"], 32);
+        assert_eq!(frame["components"][1]["component_id"], 2);
+
+        assert_eq!(values[2]["tables"][1]["table_index"], 1);
+        assert_eq!(values[4]["tables"].as_array().unwrap().len(), 4);
+        assert_eq!(values[4]["tables"][1]["table_class"], 1);
+        assert_eq!(values[5]["count"], 2);
+        assert_eq!(values[6]["components"][2]["ac_table_index"], 1);
+        Ok(())
+    }
+}
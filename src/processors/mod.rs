@@ -1,7 +1,9 @@
 mod debug;
 mod dht_reader;
 mod dht_writer;
+mod json;
 
 pub use debug::DebugReader;
 pub use dht_reader::DhtReader;
-pub use dht_writer::DhtWriter;
+pub use dht_writer::{Batch, DhtWriter, TableCallback};
+pub use json::JsonReader;
@@ -1,30 +1,52 @@
 use std::io::Cursor;
 
-use anyhow::Result;
+use anyhow::{bail, Result};
 use bitstream_io::{
-    huffman::{ReadHuffmanTree, WriteHuffmanTree},
+    huffman::{compile_read_tree, compile_write_tree, ReadHuffmanTree, WriteHuffmanTree},
     BigEndian, BitRead, BitReader, BitWrite, BitWriter, HuffmanRead, HuffmanWrite, Numeric,
 };
 
+use crate::huffman::construct_huffman_table;
+
 type ReadCursor<'a> = Cursor<&'a Vec<u8>>;
 type WriteCursor<'a> = Cursor<&'a mut Vec<u8>>;
 
 type HuffmanTreeReadInner = ReadHuffmanTree<BigEndian, u8>;
 type HuffmanTreeWriteInner = WriteHuffmanTree<BigEndian, u8>;
 type HuffmanTreeRead = Box<[HuffmanTreeReadInner]>;
-type HuffmanTreeWrite = Box<[HuffmanTreeWriteInner]>;
+
+/// The reads the entropy decoder makes, whether or not what's read is also written back out
+pub trait EntropyRead<'a> {
+    fn set_tables(&mut self, dc_tree: &'a HuffmanRWTree, ac_tree: &'a HuffmanRWTree);
+    fn byte_align(&mut self) -> Result<()>;
+    /// How many bits of the input have been read so far
+    fn reader_bit_position(&mut self) -> u64;
+    fn read<T: Numeric + std::fmt::Display + std::fmt::Binary>(&mut self, bits: u32) -> Result<T>;
+    fn read_huffman_dc(&mut self) -> Result<u8>;
+    fn read_huffman_ac(&mut self) -> Result<u8>;
+}
 
 pub struct RWStream<'a> {
     reader: BitReader<ReadCursor<'a>, BigEndian>,
     writer: BitWriter<WriteCursor<'a>, BigEndian>,
     dc_tree: Option<&'a HuffmanRWTree>,
     ac_tree: Option<&'a HuffmanRWTree>,
+    /// Whether what's read is written back out as it is
+    mirror: bool,
+}
+
+/// As `RWStream`, without re-encoding what's read
+pub struct ReadStream<'a> {
+    reader: BitReader<ReadCursor<'a>, BigEndian>,
+    dc_tree: Option<&'a HuffmanRWTree>,
+    ac_tree: Option<&'a HuffmanRWTree>,
 }
 
-#[derive(Default)]
 pub struct HuffmanRWTree {
     reader: HuffmanTreeRead,
-    writer: HuffmanTreeWrite,
+    writer: HuffmanTreeWriteInner,
+    /// The values `writer` has codes for
+    write_values: Vec<u8>,
 }
 
 impl<'a> RWStream<'a> {
@@ -38,55 +60,170 @@ impl<'a> RWStream<'a> {
             writer,
             dc_tree: None,
             ac_tree: None,
+            mirror: true,
+        }
+    }
+
+    /// Stops (or restarts) writing out what's read, for when the output is to differ. The reads
+    /// still advance the input as usual.
+    pub fn set_mirror(&mut self, mirror: bool) {
+        self.mirror = mirror;
+    }
+
+    pub fn write<T: Numeric>(&mut self, bits: u32, value: T) -> Result<()> {
+        Ok(self.writer.write(bits, value)?)
+    }
+
+    pub fn write_huffman_dc(&mut self, value: u8) -> Result<()> {
+        let tree = self.dc_tree.unwrap();
+        if !tree.write_values.contains(&value) {
+            bail!("DC table has no code for category {value}");
         }
+        Ok(self.writer.write_huffman(tree.writer(), value)?)
+    }
+
+    /// [SPEC] F.1.2.3 -- Fills out the output's last byte with 1-bits, as ahead of a marker
+    pub fn pad_writer(&mut self) -> Result<()> {
+        while !self.writer.byte_aligned() {
+            self.writer.write_bit(true)?;
+        }
+        Ok(())
     }
 
     pub fn writer_position(&mut self) -> usize {
         self.writer.writer().unwrap().position() as usize
     }
+}
 
-    pub fn set_tables(&mut self, dc_tree: &'a HuffmanRWTree, ac_tree: &'a HuffmanRWTree) {
+impl<'a> EntropyRead<'a> for RWStream<'a> {
+    fn set_tables(&mut self, dc_tree: &'a HuffmanRWTree, ac_tree: &'a HuffmanRWTree) {
         self.dc_tree = Some(dc_tree);
         self.ac_tree = Some(ac_tree);
     }
 
-    pub fn byte_align(&mut self) -> Result<()> {
+    fn byte_align(&mut self) -> Result<()> {
         self.reader.byte_align();
-        self.writer.byte_align()?;
+        if self.mirror {
+            self.writer.byte_align()?;
+        }
         Ok(())
     }
 
-    pub fn read<T: Numeric + std::fmt::Display + std::fmt::Binary>(
-        &mut self,
-        bits: u32,
-    ) -> Result<T> {
+    fn reader_bit_position(&mut self) -> u64 {
+        // Seeking a cursor can't fail
+        self.reader.position_in_bits().unwrap()
+    }
+
+    fn read<T: Numeric + std::fmt::Display + std::fmt::Binary>(&mut self, bits: u32) -> Result<T> {
         let value = self.reader.read(bits)?;
-        self.writer.write::<T>(bits, value)?;
+        if self.mirror {
+            self.writer.write::<T>(bits, value)?;
+        }
         Ok(value)
     }
 
-    pub fn read_huffman_dc(&mut self) -> Result<u8> {
+    fn read_huffman_dc(&mut self) -> Result<u8> {
         let value = self.reader.read_huffman(self.dc_tree.unwrap().reader())?;
-
-        self.writer
-            .write_huffman(self.dc_tree.unwrap().writer(), value)?;
+        if self.mirror {
+            self.writer
+                .write_huffman(self.dc_tree.unwrap().writer(), value)?;
+        }
 
         Ok(value)
     }
 
-    pub fn read_huffman_ac(&mut self) -> Result<u8> {
+    fn read_huffman_ac(&mut self) -> Result<u8> {
         let value = self.reader.read_huffman(self.ac_tree.unwrap().reader())?;
-
-        self.writer
-            .write_huffman(self.ac_tree.unwrap().writer(), value)?;
+        if self.mirror {
+            self.writer
+                .write_huffman(self.ac_tree.unwrap().writer(), value)?;
+        }
 
         Ok(value)
     }
 }
 
+impl<'a> ReadStream<'a> {
+    pub fn new(read: &'a Vec<u8>) -> Self {
+        Self {
+            reader: BitReader::endian(Cursor::new(read), BigEndian),
+            dc_tree: None,
+            ac_tree: None,
+        }
+    }
+}
+
+impl<'a> EntropyRead<'a> for ReadStream<'a> {
+    fn set_tables(&mut self, dc_tree: &'a HuffmanRWTree, ac_tree: &'a HuffmanRWTree) {
+        self.dc_tree = Some(dc_tree);
+        self.ac_tree = Some(ac_tree);
+    }
+
+    fn byte_align(&mut self) -> Result<()> {
+        self.reader.byte_align();
+        Ok(())
+    }
+
+    fn reader_bit_position(&mut self) -> u64 {
+        // Seeking a cursor can't fail
+        self.reader.position_in_bits().unwrap()
+    }
+
+    fn read<T: Numeric + std::fmt::Display + std::fmt::Binary>(&mut self, bits: u32) -> Result<T> {
+        Ok(self.reader.read(bits)?)
+    }
+
+    fn read_huffman_dc(&mut self) -> Result<u8> {
+        Ok(self.reader.read_huffman(self.dc_tree.unwrap().reader())?)
+    }
+
+    fn read_huffman_ac(&mut self) -> Result<u8> {
+        Ok(self.reader.read_huffman(self.ac_tree.unwrap().reader())?)
+    }
+}
+
+impl Default for HuffmanRWTree {
+    fn default() -> Self {
+        // With no codes, there's nothing that could fail to compile
+        let writer = compile_write_tree(Vec::new()).unwrap();
+        Self::new(HuffmanTreeRead::default(), writer, Vec::new())
+    }
+}
+
 impl HuffmanRWTree {
-    pub fn new(reader: HuffmanTreeRead, writer: HuffmanTreeWrite) -> Self {
-        Self { reader, writer }
+    pub fn new(
+        reader: HuffmanTreeRead,
+        writer: HuffmanTreeWriteInner,
+        write_values: Vec<u8>,
+    ) -> Self {
+        Self {
+            reader,
+            writer,
+            write_values,
+        }
+    }
+
+    /// Builds the trees for a DHT table, given its count of codes per length and its values
+    pub fn from_table(sizes: &[u8], values: &[u8]) -> Result<Self> {
+        Self::from_tables(sizes, values, values)
+    }
+
+    /// As `from_table`, but writes each value with the code it's assigned in `write_values`
+    pub fn from_tables(sizes: &[u8], read_values: &[u8], write_values: &[u8]) -> Result<Self> {
+        let num_codes = sizes.iter().map(|&count| count as usize).sum::<usize>();
+        if sizes.len() != 16 || num_codes != read_values.len() || num_codes != write_values.len() {
+            bail!(
+                "Invalid huffman table: {num_codes} codes for {} values",
+                read_values.len()
+            );
+        }
+
+        let reader =
+            compile_read_tree::<BigEndian, _>(construct_huffman_table(sizes, read_values)?)?;
+        let writer =
+            compile_write_tree::<BigEndian, _>(construct_huffman_table(sizes, write_values)?)?;
+
+        Ok(Self::new(reader, writer, write_values.to_vec()))
     }
 
     pub fn reader(&self) -> &[HuffmanTreeReadInner] {
@@ -94,6 +231,44 @@ impl HuffmanRWTree {
     }
 
     pub fn writer(&self) -> &HuffmanTreeWriteInner {
-        &self.writer[0]
+        &self.writer
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SIZES: [u8; 16] = [0, 1, 5, 1, 1, 1, 1, 1, 1, 0, 0, 0, 0, 0, 0, 0];
+    const VALUES: [u8; 12] = [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11];
+
+    #[test]
+    fn test_from_table() -> Result<()> {
+        let tree = HuffmanRWTree::from_table(&SIZES, &VALUES)?;
+        let mut permuted = VALUES;
+        permuted.swap(1, 2);
+        let swapped = HuffmanRWTree::from_tables(&SIZES, &VALUES, &permuted)?;
+
+        // 00 -> 0, 010 -> 1, 011 -> 2, padded with 1s
+        let input = vec![0b00010011, 0b11111111];
+        let mut output = Vec::new();
+        let mut stream = RWStream::new(&input, &mut output);
+        stream.set_tables(&tree, &swapped);
+
+        assert_eq!(stream.read_huffman_dc()?, 0);
+        assert_eq!(stream.read_huffman_ac()?, 1);
+        assert_eq!(stream.read_huffman_ac()?, 2);
+        stream.byte_align()?;
+
+        // 1 and 2 now swap codes
+        assert_eq!(output, vec![0b00011010]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_from_table_invalid() {
+        assert!(HuffmanRWTree::from_table(&SIZES, &VALUES[1..]).is_err());
+        assert!(HuffmanRWTree::from_table(&SIZES[1..], &VALUES).is_err());
+        assert!(HuffmanRWTree::from_tables(&SIZES, &VALUES, &VALUES[1..]).is_err());
     }
 }
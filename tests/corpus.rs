@@ -0,0 +1,119 @@
+//! Embeds and extracts a secret in every `.jpg` of a directory of real-world files. Set
+//! `JPEG_CORPUS_DIR` to run it, e.g.
+//!
+//! > JPEG_CORPUS_DIR=path/to/jpegs cargo test --test corpus -- --nocapture
+
+use std::io::Cursor;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::path::Path;
+
+use hackathon_jpeg_steganography::{
+    capacity, coding_mode, read_secret, write_secret, CodingMode, StegError, WriteOptions,
+};
+
+enum Outcome {
+    Pass,
+    Skip(String),
+    Fail(String),
+}
+
+#[test]
+fn test_corpus() {
+    let dir = match std::env::var_os("JPEG_CORPUS_DIR") {
+        None => {
+            println!("JPEG_CORPUS_DIR isn't set, so there's no corpus to run");
+            return;
+        }
+        Some(dir) => dir,
+    };
+
+    let mut paths = std::fs::read_dir(&dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| matches!(path.extension(), Some(extension) if extension.eq_ignore_ascii_case("jpg")))
+        .collect::<Vec<_>>();
+    paths.sort();
+
+    let (mut passed, mut skipped, mut failed) = (0, 0, Vec::new());
+    for path in &paths {
+        // A panic deep in the parser is as much a failure as a wrong secret
+        let outcome = catch_unwind(AssertUnwindSafe(|| round_trip(path)))
+            .unwrap_or_else(|_| Outcome::Fail("panicked".to_string()));
+
+        match outcome {
+            Outcome::Pass => passed += 1,
+            Outcome::Skip(reason) => {
+                println!("SKIP {}: {reason}", path.display());
+                skipped += 1;
+            }
+            Outcome::Fail(reason) => {
+                println!("FAIL {}: {reason}", path.display());
+                failed.push(path.display().to_string());
+            }
+        }
+    }
+
+    println!(
+        "{passed} passed, {skipped} skipped, {} failed of {} files",
+        failed.len(),
+        paths.len()
+    );
+    assert!(failed.is_empty(), "Failed: {failed:?}");
+}
+
+fn round_trip(path: &Path) -> Outcome {
+    let input = std::fs::read(path).unwrap();
+
+    match coding_mode(&mut Cursor::new(&input)) {
+        Ok(CodingMode::Baseline | CodingMode::ExtendedSequential) => {}
+        Ok(mode) => return Outcome::Skip(format!("{mode:?} coding isn't supported")),
+        Err(error) => return unsupported_or_failed(error),
+    }
+
+    let capacity = match capacity(&mut Cursor::new(&input), &WriteOptions::default()) {
+        Ok(capacity) => capacity,
+        Err(error) => return unsupported_or_failed(error),
+    };
+    if capacity < 2 {
+        return Outcome::Skip(format!("only {capacity} bytes of capacity"));
+    }
+
+    let secret = random_bytes(capacity / 2, input.len() as u64);
+    let mut output = Vec::new();
+    let written = write_secret(
+        &mut Cursor::new(&input),
+        &mut output,
+        &secret,
+        &WriteOptions::default(),
+    );
+    if let Err(error) = written {
+        return Outcome::Fail(format!("write failed: {error}"));
+    }
+
+    match read_secret(&mut Cursor::new(&output)) {
+        Ok(Some(read)) if read == secret => Outcome::Pass,
+        Ok(Some(_)) => Outcome::Fail("read back a different secret".to_string()),
+        Ok(None) => Outcome::Fail("no secret read back".to_string()),
+        Err(error) => Outcome::Fail(format!("read failed: {error}")),
+    }
+}
+
+fn unsupported_or_failed(error: anyhow::Error) -> Outcome {
+    match error.downcast_ref::<StegError>() {
+        Some(StegError::NotAJpeg | StegError::Progressive) => Outcome::Skip(error.to_string()),
+        _ => Outcome::Fail(error.to_string()),
+    }
+}
+
+/// xorshift64, seeded per file so a failure can be reproduced
+fn random_bytes(len: usize, seed: u64) -> Vec<u8> {
+    let mut state = seed | 1;
+    (0..len)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            (state >> 56) as u8
+        })
+        .collect()
+}